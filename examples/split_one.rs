@@ -25,10 +25,12 @@ fn main() -> anyhow::Result<()> {
         SplitProgress::Stage(s) => {
             eprintln!("> {}", s);
         }
+        SplitProgress::FileStarted { .. } | SplitProgress::FileFinished { .. } => {}
         SplitProgress::Chunks {
             done,
             total,
             percent,
+            ..
         } => {
             eprint!("\rSplit: {}/{} ({:.0}%)", done, total, percent);
             if done >= total {
@@ -52,12 +54,13 @@ fn main() -> anyhow::Result<()> {
         output_dir: out,
         model_name: "htdemucs_ort_v1".into(),
         manifest_url_override: None,
+        ..Default::default()
     };
 
     let res = stem_splitter_core::split_file(&input, opts)?;
-    eprintln!(
-        "Done:\n{}\n{}\n{}\n{}",
-        res.vocals_path, res.drums_path, res.bass_path, res.other_path
-    );
+    eprintln!("Done:");
+    for stem in res.stems.values() {
+        eprintln!("{}", stem.path);
+    }
     Ok(())
 }
@@ -1,8 +1,10 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::process;
 use stem_splitter_core::{
-    prepare_model, set_download_progress_callback, set_split_progress_callback, split_file,
-    SplitOptions, SplitProgress,
+    list_tracks, prepare_model, set_download_progress_callback, set_split_progress_callback,
+    split_file, split_files, CancelToken, ComplementMethod, GainStaging, MixConsistency,
+    MultichannelMode, OutputFormat, OverlapWindow, SplitOptions, SplitProgress, SplitResult,
+    TrackSelector,
 };
 
 #[derive(Parser)]
@@ -16,13 +18,9 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    Split {
-        #[arg(short, long)]
-        input: String,
-
-        #[arg(short, long, default_value = ".")]
-        output: String,
+    Split(Box<SplitArgs>),
 
+    Prepare {
         #[arg(short, long, default_value = "htdemucs_ort_v1")]
         model: String,
 
@@ -33,38 +31,298 @@ enum Commands {
         quiet: bool,
     },
 
-    Prepare {
-        #[arg(short, long, default_value = "htdemucs_ort_v1")]
-        model: String,
-
-        #[arg(long)]
-        manifest_url: Option<String>,
+    /// List available models
+    List,
 
+    /// List the audio tracks of an input file
+    Tracks {
+        /// Input file
         #[arg(short, long)]
-        quiet: bool,
+        input: String,
     },
+}
 
-    /// List available models
-    List,
+#[derive(Args)]
+struct SplitArgs {
+    /// Input file(s); several inputs are split as a batch with one model load
+    #[arg(short, long, num_args = 1.., required = true)]
+    input: Vec<String>,
+
+    #[arg(short, long, default_value = ".")]
+    output: String,
+
+    #[arg(short, long, default_value = "htdemucs_ort_v1")]
+    model: String,
+
+    #[arg(long)]
+    manifest_url: Option<String>,
+
+    /// Resample stems back to the input file's sample rate
+    #[arg(long)]
+    resample_to_source: bool,
+
+    /// Fraction of each window shared with the next one (0 <= overlap < 1)
+    #[arg(long)]
+    overlap: Option<f32>,
+
+    /// Weighting used to blend overlapping windows
+    #[arg(long, value_enum, default_value_t = OverlapWindowArg::Triangular)]
+    overlap_window: OverlapWindowArg,
+
+    /// Number of randomly shifted passes to average (0 disables shifting)
+    #[arg(long, default_value_t = 0)]
+    shifts: usize,
+
+    /// Also average a polarity-inverted pass
+    #[arg(long)]
+    flip_polarity: bool,
+
+    /// Also average a pass with left and right swapped
+    #[arg(long)]
+    flip_channels: bool,
+
+    /// Seed for the shift offsets, for reproducible output
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Only write these stems (comma-separated, e.g. vocals,drums)
+    #[arg(long, value_delimiter = ',')]
+    stems: Vec<String>,
+
+    /// Write only STEM and no_STEM (everything else)
+    #[arg(long, value_name = "STEM")]
+    two_stems: Option<String>,
+
+    /// How the no_STEM track is built in two-stem mode
+    #[arg(long, value_enum, default_value_t = ComplementArg::Sum)]
+    complement: ComplementArg,
+
+    /// Output file format and bit depth
+    #[arg(long, value_enum, default_value_t = FormatArg::Wav16)]
+    format: FormatArg,
+
+    /// Only split from this many seconds into the input
+    #[arg(long, value_name = "SECONDS")]
+    start: Option<f64>,
+
+    /// Stop splitting this many seconds into the input
+    #[arg(long, value_name = "SECONDS")]
+    end: Option<f64>,
+
+    /// Windows per model call (default: chosen for the execution provider)
+    #[arg(long, value_name = "N")]
+    batch_size: Option<usize>,
+
+    /// Keep a checkpoint next to the stems so an interrupted split resumes
+    #[arg(long)]
+    checkpoint: bool,
+
+    /// Keep stems from clipping: one gain for all stems, per-stem peak or
+    /// loudness normalization, or a limiter
+    #[arg(long, value_enum, default_value_t = GainArg::Off)]
+    gain: GainArg,
+
+    /// Target for --gain in dB: the peak ceiling (default -1 dBTP) or the
+    /// loudness for `loudness` (default -23 LUFS)
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    gain_target: Option<f32>,
+
+    /// Refine stems with this many iterations of a multichannel Wiener
+    /// filter to reduce bleed (0 disables it)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    wiener_iterations: usize,
+
+    /// Spread the difference between the input and the summed stems over the
+    /// stems, so they add back up to the input
+    #[arg(long, value_enum, default_value_t = ConsistencyArg::Off)]
+    consistency: ConsistencyArg,
+
+    /// Write windows whose input is silent as silence without running the
+    /// model
+    #[arg(long)]
+    skip_silence: bool,
+
+    /// Peak level below which `--skip-silence` treats a window as silent
+    #[arg(
+        long,
+        value_name = "DBFS",
+        default_value_t = -80.0,
+        allow_negative_numbers = true
+    )]
+    silence_threshold: f32,
+
+    /// Audio track to split: its index among the file's audio tracks (see
+    /// the `tracks` command) or a language code such as `eng`
+    #[arg(long, value_name = "INDEX|LANG", default_value = "default")]
+    track: TrackSelector,
+
+    /// How inputs with more than two channels (5.1, 7.1) are separated
+    #[arg(long, value_enum, default_value_t = MultichannelArg::Downmix)]
+    multichannel: MultichannelArg,
+
+    /// Channels to separate with `--multichannel pair`, counted from 0
+    /// (e.g. `2,3`)
+    #[arg(
+        long,
+        value_name = "LEFT,RIGHT",
+        value_parser = parse_channel_pair,
+        required_if_eq("multichannel", "pair")
+    )]
+    channel_pair: Option<(usize, usize)>,
+
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GainArg {
+    Off,
+    Common,
+    Peak,
+    Loudness,
+    Limit,
+}
+
+impl GainArg {
+    fn staging(self, target: Option<f32>) -> GainStaging {
+        let ceiling_db = target.unwrap_or(-1.0);
+        match self {
+            GainArg::Off => GainStaging::Off,
+            GainArg::Common => GainStaging::Common { ceiling_db },
+            GainArg::Peak => GainStaging::NormalizePeak {
+                peak_db: ceiling_db,
+            },
+            GainArg::Loudness => GainStaging::NormalizeLoudness {
+                lufs: target.unwrap_or(-23.0),
+            },
+            GainArg::Limit => GainStaging::Limit { ceiling_db },
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OverlapWindowArg {
+    Triangular,
+    Linear,
+}
+
+impl From<OverlapWindowArg> for OverlapWindow {
+    fn from(arg: OverlapWindowArg) -> Self {
+        match arg {
+            OverlapWindowArg::Triangular => OverlapWindow::Triangular,
+            OverlapWindowArg::Linear => OverlapWindow::Linear,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ComplementArg {
+    /// Sum of the other stems
+    Sum,
+    /// Input mixture minus the stem
+    Minus,
+}
+
+impl From<ComplementArg> for ComplementMethod {
+    fn from(arg: ComplementArg) -> Self {
+        match arg {
+            ComplementArg::Sum => ComplementMethod::Sum,
+            ComplementArg::Minus => ComplementMethod::Minus,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConsistencyArg {
+    /// Leave the stems as separated
+    Off,
+    /// Give every stem the same share
+    Equal,
+    /// Share in proportion to each stem's energy
+    Energy,
+}
+
+impl From<ConsistencyArg> for MixConsistency {
+    fn from(arg: ConsistencyArg) -> Self {
+        match arg {
+            ConsistencyArg::Off => MixConsistency::Off,
+            ConsistencyArg::Equal => MixConsistency::Equal,
+            ConsistencyArg::Energy => MixConsistency::Energy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MultichannelArg {
+    /// Downmix to stereo (ITU-R BS.775) and write stereo stems
+    Downmix,
+    /// Separate each channel pair and keep the input's channels
+    PerPair,
+    /// Separate only the channels given by --channel-pair
+    Pair,
+}
+
+impl MultichannelArg {
+    fn mode(self, pair: Option<(usize, usize)>) -> MultichannelMode {
+        match (self, pair) {
+            (MultichannelArg::Pair, Some((left, right))) => MultichannelMode::Pair { left, right },
+            (MultichannelArg::PerPair, _) => MultichannelMode::PerPair,
+            _ => MultichannelMode::Downmix,
+        }
+    }
+}
+
+fn parse_channel_pair(s: &str) -> Result<(usize, usize), String> {
+    let parse = |channel: &str| {
+        channel
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("expected LEFT,RIGHT channel numbers, got '{}'", s))
+    };
+    match s.split_once(',') {
+        Some((left, right)) => Ok((parse(left)?, parse(right)?)),
+        None => Err(format!("expected LEFT,RIGHT channel numbers, got '{}'", s)),
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+    /// 16-bit WAV (dithered)
+    Wav16,
+    /// 24-bit WAV
+    Wav24,
+    /// 32-bit float WAV
+    WavF32,
+    /// 16-bit FLAC (dithered)
+    Flac16,
+    /// 24-bit FLAC
+    Flac24,
+}
+
+impl From<FormatArg> for OutputFormat {
+    fn from(arg: FormatArg) -> Self {
+        match arg {
+            FormatArg::Wav16 => OutputFormat::Wav16,
+            FormatArg::Wav24 => OutputFormat::Wav24,
+            FormatArg::WavF32 => OutputFormat::WavF32,
+            FormatArg::Flac16 => OutputFormat::Flac16,
+            FormatArg::Flac24 => OutputFormat::Flac24,
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
     let result = match cli.command {
-        Commands::Split {
-            input,
-            output,
-            model,
-            manifest_url,
-            quiet,
-        } => handle_split(input, output, model, manifest_url, quiet),
+        Commands::Split(args) => handle_split(*args),
         Commands::Prepare {
             model,
             manifest_url,
             quiet,
         } => handle_prepare(model, manifest_url, quiet),
         Commands::List => handle_list(),
+        Commands::Tracks { input } => handle_tracks(&input),
     };
 
     match result {
@@ -76,15 +334,43 @@ fn main() {
     }
 }
 
-fn handle_split(
-    input: String,
-    output: String,
-    model: String,
-    manifest_url: Option<String>,
-    quiet: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !std::path::Path::new(&input).exists() {
-        return Err(format!("Input file not found: {}", input).into());
+fn handle_split(args: SplitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let SplitArgs {
+        input,
+        output,
+        model,
+        manifest_url,
+        resample_to_source,
+        overlap,
+        overlap_window,
+        shifts,
+        flip_polarity,
+        flip_channels,
+        seed,
+        stems,
+        two_stems,
+        complement,
+        format,
+        start,
+        end,
+        batch_size,
+        checkpoint,
+        gain,
+        gain_target,
+        wiener_iterations,
+        consistency,
+        skip_silence,
+        silence_threshold,
+        track,
+        multichannel,
+        channel_pair,
+        quiet,
+    } = args;
+
+    if let [single] = input.as_slice() {
+        if !std::path::Path::new(single).exists() {
+            return Err(format!("Input file not found: {}", single).into());
+        }
     }
 
     if !quiet {
@@ -95,40 +381,186 @@ fn handle_split(
         output_dir: output.clone(),
         model_name: model.clone(),
         manifest_url_override: manifest_url,
+        resample_to_source_rate: resample_to_source,
+        overlap,
+        overlap_window: overlap_window.into(),
+        shifts,
+        flip_polarity,
+        flip_channels,
+        seed,
+        stems,
+        two_stems,
+        complement: complement.into(),
+        output_format: format.into(),
+        start,
+        end,
+        batch_size,
+        checkpoint,
+        gain: gain.staging(gain_target),
+        wiener_iterations,
+        consistency: consistency.into(),
+        skip_silence: skip_silence.then_some(silence_threshold),
+        track,
+        multichannel: multichannel.mode(channel_pair),
+        cancel: CancelToken::default(),
     };
 
     if !quiet {
         eprintln!("🎵 Stem Splitter");
         eprintln!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-        eprintln!("Input:  {}", input);
+        match input.as_slice() {
+            [single] => eprintln!("Input:  {}", single),
+            many => eprintln!("Input:  {} files", many.len()),
+        }
         eprintln!("Output: {}", output);
         eprintln!("Model:  {}", model);
+        if start.is_some() || end.is_some() {
+            eprintln!(
+                "Range:  {:.2}s - {}",
+                start.unwrap_or(0.0),
+                end.map_or("end".to_string(), |end| format!("{:.2}s", end))
+            );
+        }
         eprintln!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
         eprintln!();
     }
 
-    let result = split_file(&input, opts)?;
+    if input.len() > 1 {
+        return split_batch(&input, opts, quiet);
+    }
+
+    let result = split_file(&input[0], opts)?;
 
     if !quiet {
         eprintln!();
         eprintln!("✅ Split completed successfully!");
         eprintln!();
         eprintln!("Output files:");
-        eprintln!("  🎤 Vocals: {}", result.vocals_path);
-        eprintln!("  🥁 Drums:  {}", result.drums_path);
-        eprintln!("  🎸 Bass:   {}", result.bass_path);
-        eprintln!("  🎹 Other:  {}", result.other_path);
+        print_stems(&result);
     } else {
         // Quiet mode: just print paths
-        println!("{}", result.vocals_path);
-        println!("{}", result.drums_path);
-        println!("{}", result.bass_path);
-        println!("{}", result.other_path);
+        for stem in result.stems.values() {
+            println!("{}", stem.path);
+        }
+    }
+
+    Ok(())
+}
+
+fn split_batch(
+    inputs: &[String],
+    opts: SplitOptions,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let batch = split_files(inputs, opts)?;
+
+    if !quiet {
+        eprintln!();
+        for item in &batch.items {
+            match &item.result {
+                Ok(result) => {
+                    eprintln!("✅ {}", item.input);
+                    print_stems(result);
+                }
+                Err(e) => eprintln!("❌ {}: {}", item.input, e),
+            }
+        }
+        eprintln!();
+    } else {
+        for (_, result) in batch.succeeded() {
+            for stem in result.stems.values() {
+                println!("{}", stem.path);
+            }
+        }
     }
 
+    let failed = batch.failed().count();
+    if failed > 0 {
+        return Err(format!("{} of {} files failed", failed, batch.items.len()).into());
+    }
+    if !quiet {
+        eprintln!("✅ Split {} files successfully!", batch.items.len());
+    }
     Ok(())
 }
 
+fn print_stems(result: &SplitResult) {
+    let width = result
+        .stems
+        .keys()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, stem) in &result.stems {
+        let label = format!("{}:", capitalize(name));
+        eprintln!(
+            "  {} {:<width$} {}",
+            stem_icon(name),
+            label,
+            stem.path,
+            width = width + 1
+        );
+        let stats = &stem.stats;
+        let clipped = match stats.clipped_samples {
+            0 => String::new(),
+            n => format!(", ⚠️  {} clipped samples", n),
+        };
+        let gain = if stem.gain_db != 0.0 {
+            format!(", gain {:+.1} dB", stem.gain_db)
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "     {:<width$} {:.1} LUFS, peak {:.1} dBTP{}{}",
+            "",
+            stats.integrated_lufs,
+            to_db(stats.true_peak),
+            gain,
+            clipped,
+            width = width + 1
+        );
+    }
+    eprintln!("  Mix residual: {:.1} dB", result.mix_residual.relative_db);
+    if result.silent_windows > 0 {
+        eprintln!(
+            "  Silent windows: {} of {} skipped",
+            result.silent_windows, result.windows
+        );
+    }
+    let damage = result.decode_damage;
+    if !damage.is_clean() {
+        eprintln!(
+            "  ⚠️  Skipped {} corrupt packets ({} frames) while decoding the input",
+            damage.skipped_packets, damage.dropped_frames
+        );
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+fn stem_icon(name: &str) -> &'static str {
+    match name {
+        "vocals" => "🎤",
+        "drums" => "🥁",
+        "bass" => "🎸",
+        "guitar" => "🎸",
+        "piano" => "🎹",
+        "other" => "🎹",
+        name if name.starts_with("no_") => "🎶",
+        _ => "🎼",
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
 fn handle_prepare(
     model: String,
     manifest_url: Option<String>,
@@ -196,6 +628,28 @@ fn handle_list() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn handle_tracks(input: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let tracks = list_tracks(input)?;
+    if tracks.is_empty() {
+        return Err(format!("No audio tracks in {}", input).into());
+    }
+
+    for track in tracks {
+        let describe = |value: Option<String>| value.unwrap_or_else(|| "?".to_string());
+        println!(
+            "{}: {}, {} ch, {} Hz, language {}{}",
+            track.index,
+            track.codec.as_deref().unwrap_or("unsupported codec"),
+            describe(track.channels.map(|c| c.to_string())),
+            describe(track.sample_rate.map(|r| r.to_string())),
+            track.language.as_deref().unwrap_or("unknown"),
+            if track.default { " (default)" } else { "" }
+        );
+    }
+
+    Ok(())
+}
+
 fn setup_progress_callbacks() {
     set_download_progress_callback(|downloaded, total| {
         if total > 0 {
@@ -225,20 +679,40 @@ fn setup_progress_callbacks() {
                     "engine_preload" => "Loading model",
                     "read_audio" => "Reading audio file",
                     "infer" => "Processing audio",
+                    "resume_checkpoint" => "Resuming from checkpoint",
                     "write_stems" => "Writing stems",
                     "finalize" => "Finalizing",
                     _ => stage,
                 };
                 eprintln!("⏳ {}", stage_name);
             }
+            SplitProgress::FileStarted {
+                input,
+                index,
+                total,
+            } => {
+                eprintln!();
+                eprintln!("🎵 [{}/{}] {}", index, total, input);
+            }
+            SplitProgress::FileFinished {
+                error: Some(error), ..
+            } => {
+                eprintln!("❌ {}", error);
+            }
+            SplitProgress::FileFinished { .. } => {}
             SplitProgress::Chunks {
                 done,
                 total,
                 percent,
+                silent_windows,
             } => {
+                let silent = match silent_windows {
+                    0 => String::new(),
+                    n => format!(", {} silent windows skipped", n),
+                };
                 eprint!(
-                    "\r🔄 Processing: {}/{} chunks ({:.0}%)",
-                    done, total, percent
+                    "\r🔄 Processing: {}/{} chunks ({:.0}%){}",
+                    done, total, percent, silent
                 );
                 if done >= total {
                     eprintln!();
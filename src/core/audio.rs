@@ -1,19 +1,84 @@
-use std::{fs::File, io::BufWriter, path::Path};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use symphonia::core::{
-    audio::SampleBuffer, codecs::DecoderOptions, formats::FormatOptions, io::MediaSourceStream,
-    meta::MetadataOptions, probe::Hint,
+    audio::SampleBuffer,
+    codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::{Hint, ProbeResult},
+    units::{Time, TimeBase},
 };
 use symphonia::default::{get_codecs, get_probe};
 
-use crate::types::AudioData;
+use crate::{
+    core::{flac::FlacWriter, tags},
+    types::{AudioData, AudioMetadata, AudioTrack, DecodeDamage, OutputFormat, TrackSelector},
+};
 
 pub type WavWriter = hound::WavWriter<BufWriter<File>>;
 
-pub fn read_audio<P: AsRef<Path>>(path: P) -> Result<AudioData> {
-    let path: &Path = path.as_ref();
+/// Mixed with the output file name, so that repeated exports of the same audio
+/// are bit-identical while stems written side by side get independent dither.
+const DITHER_SEED: u64 = 0x5eed_d17e;
+
+/// Frames decoded and discarded ahead of a seek target, so that codecs with
+/// overlapping transforms (AAC, Vorbis) have warmed up by the target frame.
+const SEEK_PREROLL_FRAMES: u64 = 4096;
+
+/// Corrupt packets in a row after which a file is treated as unreadable
+/// rather than damaged.
+const MAX_CONSECUTIVE_DECODE_ERRORS: usize = 100;
+
+/// Encoder priming and length of the program material, in frames, for
+/// containers whose demuxer does not trim them.
+#[derive(Clone, Copy, Debug)]
+struct GaplessTrim {
+    priming: u64,
+    frames: u64,
+}
 
+/// Incremental decoder over the default track of an audio file.
+///
+/// Packets are decoded on demand, so callers can pull interleaved samples in
+/// blocks without holding the whole track in memory. Encoder delay and
+/// padding recorded in the file (LAME/Xing and iTunSMPB tags, Ogg granule
+/// positions) are trimmed, so frame 0 is the first sample of the program
+/// material.
+pub struct AudioReader {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_rate: u32,
+    channels: u16,
+    n_frames: Option<u64>,
+    metadata: AudioMetadata,
+    /// Frame index of the first sample in `pending`.
+    position: u64,
+    pending: Vec<f32>,
+    sample_buf: Option<SampleBuffer<f32>>,
+    finished: bool,
+    damage: DecodeDamage,
+    /// Frames in the last decoded packet, the length assumed for skipped
+    /// packets whose container does not give one.
+    last_packet_frames: u64,
+    /// Timestamp the next packet should start at, when timestamps are
+    /// sample-accurate; a later one means the demuxer resynchronised past
+    /// unreadable data.
+    next_ts: Option<u64>,
+    gapless: Option<GaplessTrim>,
+}
+
+/// Probe the container of `path`.
+fn probe(path: &Path) -> Result<ProbeResult> {
     let file: File =
         File::open(path).with_context(|| format!("Failed to open audio file: {:?}", path))?;
 
@@ -25,32 +90,487 @@ pub fn read_audio<P: AsRef<Path>>(path: P) -> Result<AudioData> {
         hint.with_extension(ext);
     }
 
-    let probed = get_probe().format(
+    match get_probe().format(
         &hint,
         mss,
-        &FormatOptions::default(),
+        &FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        },
         &MetadataOptions::default(),
-    )?;
+    ) {
+        Ok(probed) => Ok(probed),
+        Err(SymphoniaError::Unsupported(what)) => {
+            Err(anyhow!("Unsupported audio format: {:?} ({})", path, what))
+        }
+        Err(e) => Err(anyhow!(e).context(format!("Failed to read audio file: {:?}", path))),
+    }
+}
 
-    let mut format = probed.format;
-    let track = format.default_track().context("No default track found")?;
+/// Tracks of a container that carry audio. Video and subtitle tracks have no
+/// sample rate; audio tracks without a decoder are kept so that indices do
+/// not shift with the codecs this build supports.
+fn audio_tracks(tracks: &[Track]) -> impl Iterator<Item = &Track> {
+    tracks.iter().filter(|track| {
+        track.codec_params.sample_rate.is_some() || track.codec_params.codec != CODEC_TYPE_NULL
+    })
+}
 
-    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+/// The first audio track with a decoder, or else the first audio track.
+/// Symphonia's own default is the first track of any kind, which is usually
+/// video in Matroska and MP4 files.
+fn default_track(tracks: &[Track]) -> Option<&Track> {
+    audio_tracks(tracks)
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .or_else(|| audio_tracks(tracks).next())
+}
 
-    let mut samples: Vec<f32> = Vec::new();
-    let mut sample_rate: u32 = 0;
-    let mut channels: u16 = 0;
+/// Find the track `selector` picks among the tracks of `format`.
+fn select_track<'a>(
+    format: &'a dyn FormatReader,
+    selector: &TrackSelector,
+    path: &Path,
+) -> Result<&'a Track> {
+    match selector {
+        TrackSelector::Default => default_track(format.tracks())
+            .ok_or_else(|| anyhow!("No audio track found in {:?}", path)),
+        TrackSelector::Index(index) => audio_tracks(format.tracks()).nth(*index).ok_or_else(|| {
+            anyhow!(
+                "No audio track {} in {:?}, which has {} audio tracks",
+                index,
+                path,
+                audio_tracks(format.tracks()).count()
+            )
+        }),
+        TrackSelector::Language(language) => audio_tracks(format.tracks())
+            .find(|track| {
+                track
+                    .language
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(language))
+            })
+            .ok_or_else(|| {
+                let languages: Vec<&str> = audio_tracks(format.tracks())
+                    .filter_map(|track| track.language.as_deref())
+                    .collect();
+                anyhow!(
+                    "No audio track in language '{}' in {:?} (available: {})",
+                    language,
+                    path,
+                    if languages.is_empty() {
+                        "none tagged".to_string()
+                    } else {
+                        languages.join(", ")
+                    }
+                )
+            }),
+    }
+}
+
+/// List the audio tracks of `path` with their codec, channels, sample rate
+/// and language, in container order.
+pub fn list_tracks<P: AsRef<Path>>(path: P) -> Result<Vec<AudioTrack>> {
+    let probed = probe(path.as_ref())?;
+    let format = probed.format;
+    let default_id = default_track(format.tracks()).map(|track| track.id);
+
+    Ok(audio_tracks(format.tracks())
+        .enumerate()
+        .map(|(index, track)| {
+            let params = &track.codec_params;
+            AudioTrack {
+                index,
+                id: track.id,
+                codec: get_codecs()
+                    .get_codec(params.codec)
+                    .map(|codec| codec.short_name.to_string()),
+                channels: params
+                    .channels
+                    .or_else(|| params.channel_layout.map(|layout| layout.into_channels()))
+                    .map(|c| c.count() as u16)
+                    .or_else(|| aac_channel_count(params)),
+                sample_rate: params.sample_rate,
+                language: track.language.clone(),
+                default: Some(track.id) == default_id,
+            }
+        })
+        .collect())
+}
+
+/// Channel count from an AAC AudioSpecificConfig, which MP4 leaves to the
+/// decoder: 5 bits of object type, 4 of sample rate index (24 more for an
+/// explicit rate) and 4 of channel configuration.
+fn aac_channel_count(params: &CodecParameters) -> Option<u16> {
+    if params.codec != CODEC_TYPE_AAC {
+        return None;
+    }
+    let config = params.extra_data.as_deref()?;
+    let bits = config
+        .iter()
+        .take(5)
+        .fold(0u64, |acc, &byte| (acc << 8) | byte as u64)
+        << (8 * (8 - config.len().min(5)));
+    let field = |offset: u32, len: u32| (bits >> (64 - offset - len)) & ((1 << len) - 1);
+    if field(0, 5) == 31 {
+        return None;
+    }
+    let offset = if field(5, 4) == 15 { 33 } else { 9 };
+    match field(offset, 4) {
+        channels @ 1..=6 => Some(channels as u16),
+        7 => Some(8),
+        _ => None,
+    }
+}
+
+impl AudioReader {
+    /// Open the default track of `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_track(path, &TrackSelector::Default)
+    }
+
+    /// Open the track of `path` picked by `track`.
+    pub fn open_track<P: AsRef<Path>>(path: P, track: &TrackSelector) -> Result<Self> {
+        let path: &Path = path.as_ref();
+        let mut probed = probe(path)?;
+
+        // Tags ahead of the container (ID3v2 in MP3) come from the probe, the
+        // rest from the format reader.
+        let mut format = probed.format;
+        let mut metadata = AudioMetadata::default();
+        if let Some(rev) = probed
+            .metadata
+            .get()
+            .as_mut()
+            .and_then(|m| m.skip_to_latest())
+        {
+            tags::add_revision(&mut metadata, rev);
+        }
+        if let Some(rev) = format.metadata().skip_to_latest() {
+            tags::add_revision(&mut metadata, rev);
+        }
+        if let Err(e) = tags::read_wav_chunks(path, &mut metadata) {
+            if std::env::var("DEBUG_STEMS").is_ok() {
+                eprintln!("Ignoring unreadable tags in {:?}: {}", path, e);
+            }
+        }
+
+        let track = select_track(format.as_ref(), track, path)?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let time_base = params.time_base;
+        let mut n_frames = params.n_frames;
+
+        // The MP3 and Ogg demuxers trim encoder delay and padding themselves
+        // (LAME/Xing tags, granule positions); MP4 leaves it to iTunSMPB.
+        let itunes_gapless = tags::take_itunes_gapless(&mut metadata);
+        let gapless = match itunes_gapless {
+            Some((priming, frames)) if params.delay.is_none() => {
+                n_frames = Some(frames);
+                Some(GaplessTrim { priming, frames })
+            }
+            _ => None,
+        };
+        let sample_rate = params.sample_rate.unwrap_or(0);
+        let channels = params.channels.map(|c| c.count() as u16).unwrap_or(0);
+
+        let unsupported_codec = || {
+            anyhow!(
+                "Unsupported audio format: no decoder for the codec of {:?}",
+                path
+            )
+        };
+        if params.codec == CODEC_TYPE_NULL {
+            return Err(unsupported_codec());
+        }
+        let decoder = match get_codecs().make(&params, &DecoderOptions::default()) {
+            Ok(decoder) => decoder,
+            Err(SymphoniaError::Unsupported(_)) => return Err(unsupported_codec()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut reader = Self {
+            format,
+            decoder,
+            track_id,
+            time_base,
+            sample_rate,
+            channels,
+            n_frames,
+            metadata,
+            position: 0,
+            pending: Vec::new(),
+            sample_buf: None,
+            finished: false,
+            damage: DecodeDamage::default(),
+            last_packet_frames: 0,
+            next_ts: None,
+            gapless,
+        };
+
+        // Some containers only reveal the signal spec once a packet is decoded.
+        if reader.sample_rate == 0 || reader.channels == 0 {
+            reader.decode_next_packet()?;
+        }
+
+        Ok(reader)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Track length in frames, when the container reports it.
+    pub fn n_frames(&self) -> Option<u64> {
+        self.n_frames
+    }
+
+    /// Tags, pictures and timecode of the file.
+    pub fn metadata(&self) -> &AudioMetadata {
+        &self.metadata
+    }
+
+    /// Corrupt packets skipped so far.
+    pub fn damage(&self) -> DecodeDamage {
+        self.damage
+    }
+
+    /// Append up to `max_frames` interleaved frames to `dst`. Returns the
+    /// number of frames appended; zero means the end of the track.
+    pub fn read_frames(&mut self, max_frames: usize, dst: &mut Vec<f32>) -> Result<usize> {
+        let channels = usize::from(self.channels.max(1));
+        let wanted = max_frames * channels;
+
+        while self.pending.len() < wanted && !self.finished {
+            self.decode_next_packet()?;
+        }
+
+        let take = wanted.min(self.pending.len());
+        dst.extend_from_slice(&self.pending[..take]);
+        self.pending.drain(..take);
+        self.position += (take / channels) as u64;
+        Ok(take / channels)
+    }
 
-    while let Ok(packet) = format.next_packet() {
-        let decoded = decoder.decode(&packet)?;
-        sample_rate = decoded.spec().rate;
-        channels = decoded.spec().channels.count() as u16;
+    /// Position the reader so the next frame read is `frame`.
+    ///
+    /// Uses the container's seek support to jump to a little before `frame`,
+    /// then decodes and discards up to the exact frame. Streams that cannot
+    /// seek are decoded forward from the current position instead. Seeking
+    /// past the end leaves the reader at the end of the track.
+    pub fn seek(&mut self, frame: u64) -> Result<()> {
+        let priming = self.gapless.map_or(0, |g| g.priming);
+        let to = SeekTo::TimeStamp {
+            ts: self.frame_to_ts((frame + priming).saturating_sub(SEEK_PREROLL_FRAMES)),
+            track_id: self.track_id,
+        };
+        match self.format.seek(SeekMode::Accurate, to) {
+            Ok(seeked) => {
+                self.decoder.reset();
+                self.pending.clear();
+                self.finished = false;
+                self.next_ts = None;
+                self.position = self
+                    .ts_to_frame(seeked.actual_ts)
+                    .saturating_sub(priming)
+                    .min(frame);
+            }
+            Err(e) if frame < self.position => {
+                return Err(anyhow!("Failed to seek to frame {}: {}", frame, e));
+            }
+            // Unseekable, or past the end: decode forward instead.
+            Err(_) => {}
+        }
 
-        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
-        buffer.copy_interleaved_ref(decoded);
+        let channels = usize::from(self.channels.max(1));
+        while self.position < frame {
+            if self.pending.is_empty() {
+                if self.finished {
+                    break;
+                }
+                self.decode_next_packet()?;
+                continue;
+            }
+            let skip = (self.pending.len() / channels).min((frame - self.position) as usize);
+            self.pending.drain(..skip * channels);
+            self.position += skip as u64;
+        }
+        Ok(())
+    }
+
+    fn frame_to_ts(&self, frame: u64) -> u64 {
+        match self.time_base {
+            Some(tb) if !(tb.numer == 1 && tb.denom == self.sample_rate) => {
+                tb.calc_timestamp(Time::from(frame as f64 / self.sample_rate as f64))
+            }
+            _ => frame,
+        }
+    }
+
+    fn ts_to_frame(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(tb) if !(tb.numer == 1 && tb.denom == self.sample_rate) => {
+                let time = tb.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+            }
+            _ => ts,
+        }
+    }
+
+    /// Decode the next packet of the track into `pending`.
+    ///
+    /// Sets `finished` at the end of the stream. Packets the demuxer or
+    /// decoder reject as malformed, and gaps the demuxer skips over, are
+    /// counted in `damage`; I/O and other errors are returned.
+    fn decode_next_packet(&mut self) -> Result<()> {
+        let mut consecutive_errors = 0;
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    self.finished = true;
+                    return Ok(());
+                }
+                // A new logical stream follows (chained Ogg); only the first
+                // one is read.
+                Err(SymphoniaError::ResetRequired) => {
+                    self.finished = true;
+                    return Ok(());
+                }
+                Err(SymphoniaError::DecodeError(e)) => {
+                    self.skip_packet(None, e, &mut consecutive_errors)?;
+                    continue;
+                }
+                Err(e) => return Err(anyhow!(e).context("Failed to read audio packet")),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            if let Some(expected) = self.next_ts {
+                if packet.ts() > expected {
+                    let frames = self.ts_to_frame(packet.ts() - expected);
+                    self.record_damage(frames, "unreadable data skipped by the demuxer");
+                }
+            }
+            // Only timestamps counted in samples are exact enough to tell a
+            // gap from rounding; Matroska's are in milliseconds and often
+            // come without durations.
+            let exact = self
+                .time_base
+                .is_none_or(|tb| tb.numer == 1 && tb.denom == self.sample_rate);
+            self.next_ts = (exact && packet.dur() > 0).then(|| packet.ts() + packet.dur());
+            let start = self.ts_to_frame(packet.ts());
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    self.skip_packet(Some(packet.dur), e, &mut consecutive_errors)?;
+                    continue;
+                }
+                Err(e) => return Err(anyhow!(e).context("Failed to decode audio packet")),
+            };
+            self.last_packet_frames = decoded.frames() as u64;
+            self.sample_rate = decoded.spec().rate;
+            self.channels = decoded.spec().channels.count() as u16;
+
+            // Frames of the packet to keep.
+            let channels = usize::from(self.channels.max(1));
+            let (lo, hi) = match self.gapless {
+                None => (0, self.last_packet_frames),
+                Some(trim) => {
+                    let end = trim.priming + trim.frames;
+                    if start >= end {
+                        self.finished = true;
+                        return Ok(());
+                    }
+                    (
+                        trim.priming.saturating_sub(start),
+                        (end - start).min(self.last_packet_frames),
+                    )
+                }
+            };
+            if lo >= hi {
+                continue;
+            }
+
+            let needed = decoded.capacity() as u64;
+            let buffer = match &mut self.sample_buf {
+                Some(buf) if buf.capacity() as u64 >= needed * self.channels as u64 => buf,
+                slot => slot.insert(SampleBuffer::<f32>::new(needed, *decoded.spec())),
+            };
+            buffer.copy_interleaved_ref(decoded);
+            self.pending.extend_from_slice(
+                &buffer.samples()[lo as usize * channels..hi as usize * channels],
+            );
+            return Ok(());
+        }
+    }
+
+    /// Count a packet that could not be demuxed or decoded. `duration` is
+    /// its length in time base units, when known.
+    fn skip_packet(
+        &mut self,
+        duration: Option<u64>,
+        reason: &str,
+        consecutive_errors: &mut usize,
+    ) -> Result<()> {
+        *consecutive_errors += 1;
+        if *consecutive_errors > MAX_CONSECUTIVE_DECODE_ERRORS {
+            return Err(anyhow!(
+                "Failed to decode audio: {} corrupt packets in a row near frame {} ({})",
+                *consecutive_errors - 1,
+                self.decoded_end(),
+                reason
+            ));
+        }
+
+        let frames = match duration {
+            Some(dur) if dur > 0 => self.ts_to_frame(dur),
+            _ => self.last_packet_frames,
+        };
+        self.record_damage(frames, reason);
+        Ok(())
+    }
+
+    fn record_damage(&mut self, frames: u64, reason: &str) {
+        self.damage.skipped_packets += 1;
+        self.damage.dropped_frames += frames;
+        if std::env::var("DEBUG_STEMS").is_ok() {
+            eprintln!(
+                "Skipping corrupt audio near frame {} ({} frames): {}",
+                self.decoded_end(),
+                frames,
+                reason
+            );
+        }
+    }
 
-        samples.extend_from_slice(buffer.samples());
+    /// Frame index just past the last decoded sample.
+    fn decoded_end(&self) -> u64 {
+        self.position + (self.pending.len() / usize::from(self.channels.max(1))) as u64
     }
+}
+
+pub fn read_audio<P: AsRef<Path>>(path: P) -> Result<AudioData> {
+    read_audio_track(path, &TrackSelector::Default)
+}
+
+/// Decode the track of `path` picked by `track`.
+pub fn read_audio_track<P: AsRef<Path>>(path: P, track: &TrackSelector) -> Result<AudioData> {
+    let mut reader = AudioReader::open_track(path, track)?;
+
+    let mut samples: Vec<f32> = Vec::new();
+    while reader.read_frames(64 * 1024, &mut samples)? > 0 {}
+
+    let sample_rate = reader.sample_rate();
+    let channels = reader.channels();
+    let damage = reader.damage();
+    let metadata = reader.metadata;
 
     if std::env::var("DEBUG_STEMS").is_ok() {
         eprintln!(
@@ -66,17 +586,213 @@ pub fn read_audio<P: AsRef<Path>>(path: P) -> Result<AudioData> {
         samples,
         sample_rate,
         channels,
+        metadata,
+        damage,
     })
 }
 
 pub fn write_audio(path: &str, audio: &AudioData) -> Result<()> {
-    let mut writer = create_wav_writer(path, audio.sample_rate, audio.channels)?;
-    for sample in &audio.samples {
-        writer.write_sample(sample_to_i16(*sample))?;
+    write_audio_with_format(path, audio, OutputFormat::default())
+}
+
+pub fn write_audio_with_format(path: &str, audio: &AudioData, format: OutputFormat) -> Result<()> {
+    let mut writer = AudioWriter::create_with_metadata(
+        path,
+        audio.sample_rate,
+        audio.channels,
+        format,
+        &audio.metadata,
+    )?;
+    writer.write_samples(&audio.samples)?;
+    writer.finalize()
+}
+
+/// Triangular-PDF dither of +-1 LSB peak, added before rounding to integers.
+struct Tpdf {
+    rng: StdRng,
+}
+
+impl Tpdf {
+    /// Dither for the file at `path`. Correlated dither would add up
+    /// coherently when stems are summed, so each file name gets its own
+    /// sequence (FNV-1a, stable across builds).
+    fn for_path(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let seed = name
+            .as_encoded_bytes()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        Self {
+            rng: StdRng::seed_from_u64(seed ^ DITHER_SEED),
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        self.rng.gen::<f32>() - self.rng.gen::<f32>()
+    }
+}
+
+enum Encoder {
+    Wav(WavWriter),
+    Flac(Box<FlacWriter>),
+}
+
+/// Stem and audio file writer for every [`OutputFormat`].
+///
+/// Takes interleaved `f32` samples; quantization to the target bit depth (with
+/// dither at 16 bits) happens here.
+pub struct AudioWriter {
+    encoder: Encoder,
+    /// Integer bit depth, or `None` for float output.
+    bits: Option<u32>,
+    dither: Option<Tpdf>,
+    scratch: Vec<i32>,
+    clipped: u64,
+    /// WAV files get their tags appended once the audio is finalized.
+    wav_tags: Option<(PathBuf, AudioMetadata)>,
+}
+
+impl AudioWriter {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+        format: OutputFormat,
+    ) -> Result<Self> {
+        Self::create_with_metadata(
+            path,
+            sample_rate,
+            channels,
+            format,
+            &AudioMetadata::default(),
+        )
     }
 
-    writer.finalize()?;
-    Ok(())
+    /// Like [`AudioWriter::create`], tagging the file with `metadata`.
+    pub fn create_with_metadata<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+        format: OutputFormat,
+        metadata: &AudioMetadata,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let bits = match format {
+            OutputFormat::Wav16 | OutputFormat::Flac16 => Some(16),
+            OutputFormat::Wav24 | OutputFormat::Flac24 => Some(24),
+            OutputFormat::WavF32 => None,
+        };
+
+        let encoder = match format {
+            OutputFormat::Wav16 | OutputFormat::Wav24 | OutputFormat::WavF32 => {
+                let spec = hound::WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: bits.unwrap_or(32) as u16,
+                    sample_format: match bits {
+                        Some(_) => hound::SampleFormat::Int,
+                        None => hound::SampleFormat::Float,
+                    },
+                };
+                Encoder::Wav(hound::WavWriter::create(path, spec)?)
+            }
+            OutputFormat::Flac16 | OutputFormat::Flac24 => {
+                Encoder::Flac(Box::new(FlacWriter::create(
+                    path,
+                    sample_rate,
+                    channels,
+                    bits.unwrap_or(24) as u16,
+                    tags::flac_blocks(metadata),
+                )?))
+            }
+        };
+        let wav_tags = match &encoder {
+            Encoder::Wav(_) if !metadata.is_empty() => Some((path.to_path_buf(), metadata.clone())),
+            _ => None,
+        };
+
+        Ok(Self {
+            encoder,
+            bits,
+            dither: (bits == Some(16)).then(|| Tpdf::for_path(path)),
+            scratch: Vec::new(),
+            clipped: 0,
+            wav_tags,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<()> {
+        let Some(bits) = self.bits else {
+            if let Encoder::Wav(writer) = &mut self.encoder {
+                for sample in samples {
+                    writer.write_sample(*sample)?;
+                }
+            }
+            return Ok(());
+        };
+
+        self.scratch.clear();
+        let clipped = &mut self.clipped;
+        let mut quantize_counted = |sample: f32, dither: f32| {
+            let (value, clip) = quantize(sample, bits, dither);
+            *clipped += u64::from(clip);
+            value
+        };
+        match self.dither.as_mut() {
+            Some(dither) => self
+                .scratch
+                .extend(samples.iter().map(|s| quantize_counted(*s, dither.next()))),
+            None => self
+                .scratch
+                .extend(samples.iter().map(|s| quantize_counted(*s, 0.0))),
+        }
+
+        match &mut self.encoder {
+            Encoder::Wav(writer) => {
+                for sample in &self.scratch {
+                    writer.write_sample(*sample)?;
+                }
+            }
+            Encoder::Flac(writer) => writer.write_samples(&self.scratch)?,
+        }
+        Ok(())
+    }
+
+    /// Samples beyond full scale so far, which were clamped when written;
+    /// always zero for float output.
+    pub fn clipped_samples(&self) -> u64 {
+        self.clipped
+    }
+
+    pub fn finalize(self) -> Result<()> {
+        match self.encoder {
+            Encoder::Wav(writer) => writer.finalize()?,
+            Encoder::Flac(writer) => writer.finalize()?,
+        }
+        if let Some((path, metadata)) = self.wav_tags {
+            tags::append_wav_chunks(&path, &metadata)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scale to a signed `bits`-bit integer, adding `dither` LSBs before rounding
+/// and clamping to the representable range. Also returns whether the sample
+/// itself is out of range; dither pushing a full-scale sample over the edge
+/// is clamped but not counted.
+fn quantize(sample: f32, bits: u32, dither: f32) -> (i32, bool) {
+    let max = ((1i64 << (bits - 1)) - 1) as f64;
+    let range = -max - 1.0..=max;
+    let scaled = sample as f64 * max;
+    let clipped = !range.contains(&scaled.round());
+    let value = (scaled + dither as f64).round().clamp(-max - 1.0, max);
+    (value as i32, clipped)
 }
 
 pub fn create_wav_writer<P: AsRef<Path>>(
@@ -0,0 +1,319 @@
+//! On-disk checkpoints that let an interrupted file split continue from the
+//! last finished chunk.
+//!
+//! A checkpoint is a directory next to the stems. It holds one journal per
+//! stem with the samples emitted so far (at the model rate, before resampling
+//! and trimming) and a `state.bin` file recording how many chunks are complete
+//! together with the overlap-add tail still waiting to be emitted. Resuming
+//! replays the journals into fresh stem files and picks up inference at the
+//! next chunk, so the result matches an uninterrupted split.
+//!
+//! `state.bin` is a single line of JSON followed by the overlap-add tail as
+//! little-endian `f32`. It is replaced atomically after the journals are
+//! flushed, so journal bytes beyond what it records are simply truncated.
+//! Syncing to disk is rate-limited to once every [`SYNC_INTERVAL`]: a crash
+//! only costs re-running the chunks since then, and an interrupted split
+//! syncs its last finished chunk before it returns.
+
+use crate::{
+    core::splitter::{MixEnergy, WindowCounts},
+    error::Result,
+    io::crypto::sha256_file,
+    types::{ModelManifest, SplitOptions},
+};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const STATE_FILE: &str = "state.bin";
+const FORMAT_VERSION: u32 = 1;
+const REPLAY_BLOCK_FRAMES: usize = 64 * 1024;
+/// Shortest time between two syncs of the journals and `state.bin`.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a checkpoint was written for; it is only resumed if all of it matches.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Identity {
+    input_sha256: String,
+    model_sha256: String,
+    options: serde_json::Value,
+}
+
+impl Identity {
+    pub(crate) fn new(input_path: &str, mf: &ModelManifest, opts: &SplitOptions) -> Result<Self> {
+        // The batch size changes how windows are grouped, not what they produce.
+        let options = SplitOptions {
+            batch_size: None,
+            ..opts.clone()
+        };
+        Ok(Self {
+            input_sha256: sha256_file(Path::new(input_path))?,
+            model_sha256: mf
+                .resolve_primary_artifact()
+                .map(|a| a.sha256)
+                .unwrap_or_else(|_| mf.sha256.clone()),
+            options: serde_json::to_value(&options)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    identity: Identity,
+    stems: Vec<String>,
+    offsets: Vec<usize>,
+    chunks_done: usize,
+    /// Frames recorded in each stem's journal.
+    journal_frames: Vec<usize>,
+    mix_energy: MixEnergy,
+    #[serde(default)]
+    windows: WindowCounts,
+}
+
+/// Progress restored from a matching checkpoint.
+pub(crate) struct Resume {
+    pub stems: Vec<String>,
+    /// Test-time augmentation shift offsets the interrupted run drew.
+    pub offsets: Vec<usize>,
+    pub chunks_done: usize,
+    pub overlap_tail: Vec<f32>,
+    pub mix_energy: MixEnergy,
+    pub windows: WindowCounts,
+}
+
+pub(crate) struct Checkpoint {
+    dir: PathBuf,
+    identity: Identity,
+    resume: Option<Resume>,
+    stems: Vec<String>,
+    offsets: Vec<usize>,
+    /// Interleaved channels of every stem.
+    channels: usize,
+    journal_frames: Vec<usize>,
+    journals: Vec<BufWriter<File>>,
+    /// Latest committed progress not yet synced to disk.
+    pending: Option<(Header, Vec<f32>)>,
+    last_sync: Instant,
+}
+
+impl Checkpoint {
+    /// Use `dir` for checkpoints of a split described by `identity`, whose
+    /// stems have `channels` channels.
+    ///
+    /// A checkpoint already in `dir` is loaded for resuming if it matches;
+    /// otherwise whatever is there is discarded and the split starts over.
+    pub(crate) fn open(dir: PathBuf, identity: Identity, channels: usize) -> Self {
+        let mut checkpoint = Self {
+            dir,
+            identity,
+            resume: None,
+            stems: Vec::new(),
+            offsets: Vec::new(),
+            channels,
+            journal_frames: Vec::new(),
+            journals: Vec::new(),
+            pending: None,
+            last_sync: Instant::now(),
+        };
+
+        match checkpoint.load() {
+            Ok(Some((header, overlap_tail))) => {
+                checkpoint.journal_frames = header.journal_frames;
+                checkpoint.resume = Some(Resume {
+                    stems: header.stems,
+                    offsets: header.offsets,
+                    chunks_done: header.chunks_done,
+                    overlap_tail,
+                    mix_energy: header.mix_energy,
+                    windows: header.windows,
+                });
+            }
+            Ok(None) => {
+                fs::remove_dir_all(&checkpoint.dir).ok();
+            }
+            Err(e) => {
+                if std::env::var("DEBUG_STEMS").is_ok() {
+                    eprintln!(
+                        "Discarding unreadable checkpoint {}: {}",
+                        checkpoint.dir.display(),
+                        e
+                    );
+                }
+                fs::remove_dir_all(&checkpoint.dir).ok();
+            }
+        }
+        checkpoint
+    }
+
+    /// Read a checkpoint from disk, returning `None` if there is none or it
+    /// belongs to a different input, model or set of options.
+    fn load(&self) -> Result<Option<(Header, Vec<f32>)>> {
+        let file = match File::open(self.dir.join(STATE_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let header: Header = serde_json::from_str(&line)?;
+
+        if header.version != FORMAT_VERSION || header.identity != self.identity {
+            if std::env::var("DEBUG_STEMS").is_ok() {
+                eprintln!(
+                    "Checkpoint {} is for a different input, model or options; starting over",
+                    self.dir.display()
+                );
+            }
+            return Ok(None);
+        }
+        if header.journal_frames.len() != header.stems.len() {
+            return Err(anyhow::anyhow!("journal count does not match the stems").into());
+        }
+        for (idx, &frames) in header.journal_frames.iter().enumerate() {
+            let len = fs::metadata(self.journal_path(idx))?.len();
+            if len < (frames * self.channels * 4) as u64 {
+                return Err(anyhow::anyhow!("journal {} is shorter than recorded", idx).into());
+            }
+        }
+
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Some((header, f32s_from_le(&bytes)?)))
+    }
+
+    /// The progress to resume from, if a matching checkpoint was found.
+    pub(crate) fn take_resume(&mut self) -> Option<Resume> {
+        self.resume.take()
+    }
+
+    /// Feed the journaled samples of stem `idx` to `f`, in blocks.
+    pub(crate) fn replay(&self, idx: usize, mut f: impl FnMut(&[f32]) -> Result<()>) -> Result<()> {
+        let mut reader = BufReader::new(File::open(self.journal_path(idx))?);
+        let mut bytes = vec![0u8; REPLAY_BLOCK_FRAMES * self.channels * 4];
+        let mut remaining = self.journal_frames.get(idx).copied().unwrap_or(0);
+        while remaining > 0 {
+            let frames = remaining.min(REPLAY_BLOCK_FRAMES);
+            let block = &mut bytes[..frames * self.channels * 4];
+            reader.read_exact(block)?;
+            f(&f32s_from_le(block)?)?;
+            remaining -= frames;
+        }
+        Ok(())
+    }
+
+    /// Start journaling `stems`, keeping what an earlier run already recorded
+    /// for them.
+    pub(crate) fn begin(&mut self, stems: Vec<String>, offsets: Vec<usize>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        if self.journal_frames.len() != stems.len() {
+            self.journal_frames = vec![0; stems.len()];
+        }
+        self.journals = (0..stems.len())
+            .map(|idx| {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(self.journal_path(idx))?;
+                let len = (self.journal_frames[idx] * self.channels * 4) as u64;
+                file.set_len(len)?;
+                file.seek(SeekFrom::Start(len))?;
+                Ok(BufWriter::new(file))
+            })
+            .collect::<Result<_>>()?;
+        self.stems = stems;
+        self.offsets = offsets;
+        Ok(())
+    }
+
+    /// Journal interleaved samples emitted for stem `idx`.
+    pub(crate) fn append(&mut self, idx: usize, samples: &[f32]) -> Result<()> {
+        let journal = &mut self.journals[idx];
+        for sample in samples {
+            journal.write_all(&sample.to_le_bytes())?;
+        }
+        self.journal_frames[idx] += samples.len() / self.channels;
+        Ok(())
+    }
+
+    /// Record that `chunks_done` chunks are complete, with the overlap-add
+    /// tail they leave behind and the mix residual and window counts so far.
+    ///
+    /// The progress is synced to disk if the last sync is at least
+    /// [`SYNC_INTERVAL`] ago, and otherwise kept for a later [`Self::sync`].
+    pub(crate) fn commit(
+        &mut self,
+        chunks_done: usize,
+        overlap_tail: &[f32],
+        mix_energy: MixEnergy,
+        windows: WindowCounts,
+    ) -> Result<()> {
+        let header = Header {
+            version: FORMAT_VERSION,
+            identity: self.identity.clone(),
+            stems: self.stems.clone(),
+            offsets: self.offsets.clone(),
+            chunks_done,
+            journal_frames: self.journal_frames.clone(),
+            mix_energy,
+            windows,
+        };
+        self.pending = Some((header, overlap_tail.to_vec()));
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Write the latest committed progress to disk: the journals are synced
+    /// first, so `state.bin` never records samples that are not on disk yet.
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        let Some((header, overlap_tail)) = self.pending.take() else {
+            return Ok(());
+        };
+        for journal in &mut self.journals {
+            journal.flush()?;
+            journal.get_ref().sync_data()?;
+        }
+
+        let tmp_path = self.dir.join(format!("{}.tmp", STATE_FILE));
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut tmp, &header)?;
+        tmp.write_all(b"\n")?;
+        for sample in &overlap_tail {
+            tmp.write_all(&sample.to_le_bytes())?;
+        }
+        let tmp = tmp.into_inner().map_err(|e| e.into_error())?;
+        tmp.sync_data()?;
+        fs::rename(&tmp_path, self.dir.join(STATE_FILE))?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Delete the checkpoint once the split it covers has finished.
+    pub(crate) fn remove(self) {
+        drop(self.journals);
+        fs::remove_dir_all(&self.dir).ok();
+    }
+
+    fn journal_path(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("stem{}.f32", idx))
+    }
+}
+
+pub(crate) fn f32s_from_le(bytes: &[u8]) -> Result<Vec<f32>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(anyhow::anyhow!("truncated checkpoint data").into());
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
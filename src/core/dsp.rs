@@ -1,10 +1,12 @@
-use num_complex::Complex32;
+use num_complex::{Complex32, Complex64};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
 use rustfft::{num_traits::Zero, Fft, FftPlanner};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
+use crate::types::{MixConsistency, OverlapWindow};
+
 /// Cached FFT components
 struct FftCacheEntry {
     fft_forward: Arc<dyn Fft<f32>>,
@@ -82,6 +84,26 @@ pub fn to_planar_stereo(interleaved: &[f32], channels: u16) -> Vec<[f32; 2]> {
     }
 }
 
+/// Blend weights for one inference window of length `win` whose neighbours
+/// overlap it by `overlap` samples. All weights are strictly positive so the
+/// overlap-add normalisation never divides by zero.
+pub fn transition_weights(kind: OverlapWindow, win: usize, overlap: usize) -> Vec<f32> {
+    match kind {
+        OverlapWindow::Triangular => {
+            let peak = (win / 2).max(1) as f32;
+            (0..win)
+                .map(|i| ((i + 1).min(win - i) as f32 / peak).min(1.0))
+                .collect()
+        }
+        OverlapWindow::Linear => {
+            let ramp = (overlap + 1) as f32;
+            (0..win)
+                .map(|i| ((i + 1).min(win - i) as f32 / ramp).min(1.0))
+                .collect()
+        }
+    }
+}
+
 /// Compute complex-as-channels spectrogram for stereo with center padding.
 /// Returns (buffer, F=n_fft/2, Frames) for given input.
 /// Layout is [1, 4, F, Frames] flattened => channels order: L.re, L.im, R.re, R.im.
@@ -405,6 +427,217 @@ pub fn istft_cac_stereo_parallel(
         .collect()
 }
 
+/// Make stereo sources sum exactly to the mixture they were separated from by
+/// spreading the difference between the two over the sources.
+///
+/// `sources` holds every source planar as `[source][channel][sample]`, each
+/// channel as long as `mix_left`, and is corrected in place. With
+/// [`MixConsistency::Energy`] each channel's difference is shared in
+/// proportion to the sources' energy in that channel; if every source is
+/// silent there it is shared equally.
+pub fn mix_consistency(
+    mix_left: &[f32],
+    mix_right: &[f32],
+    sources: &mut [f32],
+    method: MixConsistency,
+) {
+    let t = mix_left.len();
+    assert_eq!(mix_right.len(), t);
+    if method == MixConsistency::Off || t == 0 || sources.is_empty() {
+        return;
+    }
+    assert_eq!(sources.len() % (2 * t), 0);
+    let count = sources.len() / (2 * t);
+
+    for (ch, mix) in [mix_left, mix_right].into_iter().enumerate() {
+        let lane = |j: usize| (2 * j + ch) * t..(2 * j + ch + 1) * t;
+        let energies: Vec<f64> = (0..count)
+            .map(|j| sources[lane(j)].iter().map(|&x| x as f64 * x as f64).sum())
+            .collect();
+        let total: f64 = energies.iter().sum();
+        let shares: Vec<f32> = energies
+            .iter()
+            .map(|&energy| match method {
+                MixConsistency::Energy if total > 0.0 => (energy / total) as f32,
+                _ => 1.0 / count as f32,
+            })
+            .collect();
+
+        for (i, &mixed) in mix.iter().enumerate() {
+            let residual = mixed - (0..count).map(|j| sources[lane(j).start + i]).sum::<f32>();
+            for (j, share) in shares.iter().enumerate() {
+                sources[lane(j).start + i] += share * residual;
+            }
+        }
+    }
+}
+
+/// Regularization added to powers and covariances in [`wiener_filter`] so
+/// silent bins never divide by zero.
+const WIENER_EPS: f64 = 1e-10;
+
+type Mat2 = [[Complex64; 2]; 2];
+
+/// Refine separated stereo sources with a multichannel Wiener filter, using
+/// the sources as priors against the mixture they were separated from.
+///
+/// `sources` holds every source planar as `[source][channel][sample]`, each
+/// channel as long as `mix_left`, and is refined in place. Every iteration
+/// fits a local Gaussian model to each source (a power per time-frequency bin
+/// and a 2x2 spatial covariance per frequency) and re-estimates the sources by
+/// filtering the mixture STFT with it, as in Open-Unmix and Demucs. The
+/// refined sources still sum to the mixture, while energy that a source's
+/// model does not explain moves to the others. `iterations == 0` leaves the
+/// sources untouched.
+pub fn wiener_filter(
+    mix_left: &[f32],
+    mix_right: &[f32],
+    sources: &mut [f32],
+    iterations: usize,
+    n_fft: usize,
+    hop: usize,
+) {
+    let t = mix_left.len();
+    assert_eq!(mix_right.len(), t);
+    if iterations == 0 || t == 0 || sources.is_empty() {
+        return;
+    }
+    assert_eq!(sources.len() % (2 * t), 0);
+    let count = sources.len() / (2 * t);
+
+    let (mix, f_bins, frames) = stft_cac_stereo_centered(mix_left, mix_right, n_fft, hop);
+    let mut specs: Vec<Vec<f32>> = sources
+        .par_chunks(2 * t)
+        .map(|source| {
+            let (left, right) = source.split_at(t);
+            stft_cac_stereo_centered(left, right, n_fft, hop).0
+        })
+        .collect();
+
+    // Frequencies are independent: the spatial covariances are fitted over
+    // time within each one.
+    let plane = f_bins * frames;
+    let bin = |spec: &[f32], ch: usize, idx: usize| {
+        Complex64::new(
+            spec[2 * ch * plane + idx] as f64,
+            spec[(2 * ch + 1) * plane + idx] as f64,
+        )
+    };
+    let refined: Vec<Vec<[Complex64; 2]>> = (0..f_bins)
+        .into_par_iter()
+        .map(|fi| {
+            let row = fi * frames..(fi + 1) * frames;
+            let mix_row: Vec<[Complex64; 2]> = row
+                .clone()
+                .map(|idx| [bin(&mix, 0, idx), bin(&mix, 1, idx)])
+                .collect();
+            let mut estimates: Vec<[Complex64; 2]> = specs
+                .iter()
+                .flat_map(|spec| {
+                    row.clone()
+                        .map(|idx| [bin(spec, 0, idx), bin(spec, 1, idx)])
+                })
+                .collect();
+            for _ in 0..iterations {
+                wiener_iteration(&mix_row, &mut estimates, count);
+            }
+            estimates
+        })
+        .collect();
+
+    for (fi, estimates) in refined.iter().enumerate() {
+        for (j, spec) in specs.iter_mut().enumerate() {
+            for fr in 0..frames {
+                let idx = fi * frames + fr;
+                let [l, r] = estimates[j * frames + fr];
+                spec[idx] = l.re as f32;
+                spec[plane + idx] = l.im as f32;
+                spec[2 * plane + idx] = r.re as f32;
+                spec[3 * plane + idx] = r.im as f32;
+            }
+        }
+    }
+
+    sources
+        .par_chunks_mut(2 * t)
+        .zip(specs.par_iter())
+        .for_each(|(source, spec)| {
+            let (left, right) = source.split_at_mut(t);
+            let mut ws = IstftStereoWorkspace::default();
+            istft_cac_stereo_into(spec, f_bins, frames, n_fft, hop, t, &mut ws, left, right);
+        });
+}
+
+/// One EM step of [`wiener_filter`] on a single frequency: `estimates` holds
+/// `count` sources of `mix.len()` frames each.
+fn wiener_iteration(mix: &[[Complex64; 2]], estimates: &mut [[Complex64; 2]], count: usize) {
+    let frames = mix.len();
+    let zero = Complex64::new(0.0, 0.0);
+
+    // Local Gaussian model of each source: power per frame and a spatial
+    // covariance normalized by the total power.
+    let mut power = vec![0.0f64; count * frames];
+    let mut spatial = vec![[[zero; 2]; 2]; count];
+    for j in 0..count {
+        let mut total = WIENER_EPS;
+        for fr in 0..frames {
+            let y = estimates[j * frames + fr];
+            let p = (y[0].norm_sqr() + y[1].norm_sqr()) / 2.0;
+            power[j * frames + fr] = p;
+            total += p;
+            for a in 0..2 {
+                for b in 0..2 {
+                    spatial[j][a][b] += y[a] * y[b].conj();
+                }
+            }
+        }
+        for entry in spatial[j].iter_mut().flatten() {
+            *entry /= total;
+        }
+    }
+
+    for fr in 0..frames {
+        let mut mix_cov: Mat2 = [
+            [Complex64::new(WIENER_EPS, 0.0), zero],
+            [zero, Complex64::new(WIENER_EPS, 0.0)],
+        ];
+        for j in 0..count {
+            let p = power[j * frames + fr];
+            for a in 0..2 {
+                for b in 0..2 {
+                    mix_cov[a][b] += spatial[j][a][b] * p;
+                }
+            }
+        }
+        let inv = invert_2x2(&mix_cov);
+        let x = mix[fr];
+        // Project the mixture through inv(C_mix) once, then through each
+        // source's covariance.
+        let projected = [
+            inv[0][0] * x[0] + inv[0][1] * x[1],
+            inv[1][0] * x[0] + inv[1][1] * x[1],
+        ];
+        for j in 0..count {
+            let p = power[j * frames + fr];
+            let r = &spatial[j];
+            estimates[j * frames + fr] = [
+                (r[0][0] * projected[0] + r[0][1] * projected[1]) * p,
+                (r[1][0] * projected[0] + r[1][1] * projected[1]) * p,
+            ];
+        }
+    }
+}
+
+fn invert_2x2(m: &Mat2) -> Mat2 {
+    // The mixture covariance is Hermitian with `WIENER_EPS` on its diagonal,
+    // so the determinant is at least `WIENER_EPS^2`.
+    let inv_det = (m[0][0] * m[1][1] - m[0][1] * m[1][0]).inv();
+    [
+        [m[1][1] * inv_det, -m[0][1] * inv_det],
+        [-m[1][0] * inv_det, m[0][0] * inv_det],
+    ]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
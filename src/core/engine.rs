@@ -27,6 +27,7 @@ use std::{
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
         Mutex,
     },
     time::Instant,
@@ -84,6 +85,8 @@ struct OrtThreading {
 #[derive(Clone, Copy)]
 struct EngineIoSpec {
     use_positional_inputs: bool,
+    /// Every input leaves its batch axis open, so windows can be stacked.
+    dynamic_batch: bool,
 }
 
 #[cfg(not(feature = "engine-mock"))]
@@ -93,7 +96,7 @@ struct EnginePerfConfig {
 }
 
 #[cfg(not(feature = "engine-mock"))]
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 struct WindowPerf {
     prep_ns: u128,
     stft_ns: u128,
@@ -209,8 +212,18 @@ fn inspect_engine_io(session: &Session) -> Result<EngineIoSpec> {
         return Err(anyhow!("Model missing output 'add_67' (time domain)").into());
     }
 
+    let dynamic_batch = !session.inputs().is_empty()
+        && session.inputs().iter().all(|input| {
+            input
+                .dtype()
+                .tensor_shape()
+                .and_then(|shape| shape.first().copied())
+                == Some(-1)
+        });
+
     Ok(EngineIoSpec {
         use_positional_inputs: use_positional_inputs(&input_names),
+        dynamic_batch,
     })
 }
 
@@ -318,20 +331,35 @@ fn commit_ep_session(
     Ok(builder.commit_from_file(model_path)?)
 }
 
+/// Shape of a stacked model call: `batch` windows of `t` samples whose
+/// spectrograms have `f_bins` x `frames` bins.
 #[cfg(not(feature = "engine-mock"))]
-fn run_demucs_raw_from_inputs(
-    session: &mut Session,
-    io_spec: &EngineIoSpec,
+#[derive(Clone, Copy)]
+struct BatchDims {
+    batch: usize,
     t: usize,
     f_bins: usize,
     frames: usize,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+fn run_demucs_raw_from_inputs(
+    session: &mut Session,
+    io_spec: &EngineIoSpec,
+    dims: BatchDims,
     time_branch: &[f32],
     spec_branch: &[f32],
     perf_enabled: bool,
     perf: &mut WindowPerf,
 ) -> Result<(Value, Value)> {
-    let time_value = TensorRef::from_array_view(([1usize, 2, t], time_branch))?;
-    let spec_value = TensorRef::from_array_view(([1usize, 4, f_bins, frames], spec_branch))?;
+    let BatchDims {
+        batch,
+        t,
+        f_bins,
+        frames,
+    } = dims;
+    let time_value = TensorRef::from_array_view(([batch, 2, t], time_branch))?;
+    let spec_value = TensorRef::from_array_view(([batch, 4, f_bins, frames], spec_branch))?;
 
     let run_start = perf_enabled.then(Instant::now);
     let mut outputs = if io_spec.use_positional_inputs {
@@ -357,27 +385,33 @@ fn run_demucs_raw_from_inputs(
     Ok((out_time, out_freq))
 }
 
+/// Split the batched model outputs into one [`DemucsRawOutput`] per window.
 #[cfg(not(feature = "engine-mock"))]
 fn decode_demucs_outputs(
     out_time: Value,
     out_freq: Value,
-    t: usize,
-    f_bins: usize,
-    frames: usize,
+    dims: BatchDims,
     perf_enabled: bool,
     perf: &mut WindowPerf,
-) -> Result<DemucsRawOutput> {
+) -> Result<Vec<DemucsRawOutput>> {
+    let BatchDims {
+        batch,
+        t,
+        f_bins,
+        frames,
+    } = dims;
     let decode_start = perf_enabled.then(Instant::now);
 
     let (shape_time, data_time) = out_time.try_extract_tensor::<f32>()?;
     if shape_time.len() != 4
-        || shape_time[0] != 1
+        || shape_time[0] != batch as i64
         || shape_time[2] != 2
         || shape_time[3] != t as i64
     {
         return Err(anyhow!(
-            "Unexpected time output shape: {:?}, expected [1, sources, 2, {}]",
+            "Unexpected time output shape: {:?}, expected [{}, sources, 2, {}]",
             shape_time,
+            batch,
             t
         )
         .into());
@@ -386,15 +420,16 @@ fn decode_demucs_outputs(
 
     let (shape_freq, data_freq) = out_freq.try_extract_tensor::<f32>()?;
     if shape_freq.len() != 5
-        || shape_freq[0] != 1
+        || shape_freq[0] != batch as i64
         || shape_freq[1] != num_sources as i64
         || shape_freq[2] != 4
         || shape_freq[3] != f_bins as i64
         || shape_freq[4] != frames as i64
     {
         return Err(anyhow!(
-            "Unexpected freq output shape: {:?}, expected [1, {}, 4, {}, {}]",
+            "Unexpected freq output shape: {:?}, expected [{}, {}, 4, {}, {}]",
             shape_freq,
+            batch,
             num_sources,
             f_bins,
             frames
@@ -402,22 +437,25 @@ fn decode_demucs_outputs(
         .into());
     }
 
-    let time_max = data_time.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
-    let freq_max = data_freq.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
-
-    let raw = DemucsRawOutput {
-        num_sources,
-        data_time: data_time.to_vec(),
-        data_freq: data_freq.to_vec(),
-        time_max,
-        freq_max,
-    };
+    let time_len = num_sources * 2 * t;
+    let freq_len = num_sources * 4 * f_bins * frames;
+    let raws = data_time
+        .chunks_exact(time_len)
+        .zip(data_freq.chunks_exact(freq_len))
+        .map(|(data_time, data_freq)| DemucsRawOutput {
+            num_sources,
+            data_time: data_time.to_vec(),
+            data_freq: data_freq.to_vec(),
+            time_max: data_time.iter().map(|x| x.abs()).fold(0.0f32, f32::max),
+            freq_max: data_freq.iter().map(|x| x.abs()).fold(0.0f32, f32::max),
+        })
+        .collect();
 
     if let Some(start) = decode_start {
         perf.decode_ns += start.elapsed().as_nanos();
     }
 
-    Ok(raw)
+    Ok(raws)
 }
 
 #[cfg(not(feature = "engine-mock"))]
@@ -479,18 +517,23 @@ fn run_demucs_raw_with_session(
     perf: &mut WindowPerf,
 ) -> Result<DemucsRawOutput> {
     let (t, f_bins, frames) = prepare_demucs_inputs(left, right, scratch, perf_enabled, perf)?;
-    let (out_time, out_freq) = run_demucs_raw_from_inputs(
-        session,
-        io_spec,
+    let dims = BatchDims {
+        batch: 1,
         t,
         f_bins,
         frames,
+    };
+    let (out_time, out_freq) = run_demucs_raw_from_inputs(
+        session,
+        io_spec,
+        dims,
         &scratch.time_branch,
         &scratch.spec_branch,
         perf_enabled,
         perf,
     )?;
-    decode_demucs_outputs(out_time, out_freq, t, f_bins, frames, perf_enabled, perf)
+    let mut raws = decode_demucs_outputs(out_time, out_freq, dims, perf_enabled, perf)?;
+    Ok(raws.remove(0))
 }
 
 #[cfg(not(feature = "engine-mock"))]
@@ -569,11 +612,18 @@ fn output_is_near_silent(time_max: f32, freq_max: f32) -> bool {
     time_max < 1e-6 && freq_max < 1e-3
 }
 
-#[cfg(not(feature = "engine-mock"))]
-fn input_is_near_silent(left: &[f32], right: &[f32]) -> bool {
+/// Sample peak of a stereo window.
+pub(crate) fn input_peak(left: &[f32], right: &[f32]) -> f32 {
     let left_max = left.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
     let right_max = right.iter().map(|x| x.abs()).fold(0.0f32, f32::max);
-    left_max.max(right_max) < 1e-4
+    left_max.max(right_max)
+}
+
+/// Whether a window is quiet enough (below -80 dBFS) that a silent model
+/// output is expected.
+#[cfg(not(feature = "engine-mock"))]
+fn input_is_near_silent(left: &[f32], right: &[f32]) -> bool {
+    input_peak(left, right) < 1e-4
 }
 
 #[cfg(not(feature = "engine-mock"))]
@@ -670,17 +720,60 @@ fn runtime_fallback_decision(
 
 #[cfg(not(feature = "engine-mock"))]
 pub fn run_window_demucs(left: &[f32], right: &[f32]) -> Result<Array3<f32>> {
-    if left.len() != right.len() {
-        return Err(anyhow!("L/R length mismatch").into());
-    }
-    if left.len() != DEMUCS_T {
-        return Err(anyhow!("Bad window length {} (expected {})", left.len(), DEMUCS_T).into());
-    }
-
     let debug_enabled = std::env::var("DEBUG_STEMS").is_ok();
     let perf_enabled = perf_config().enabled;
+    let total_start = perf_enabled.then(Instant::now);
+    let mut perf = WindowPerf::default();
+
+    let (raw, t) = {
+        let mut scratch = input_scratch().lock().expect("input scratch poisoned");
+        let (t, f_bins, frames) =
+            prepare_demucs_inputs(left, right, &mut scratch, perf_enabled, &mut perf)?;
+        let mut raws = run_with_runtime_fallback(debug_enabled, || {
+            run_prepared_batch(
+                &scratch.time_branch,
+                &scratch.spec_branch,
+                BatchDims {
+                    batch: 1,
+                    t,
+                    f_bins,
+                    frames,
+                },
+                perf_enabled,
+                &mut perf,
+            )
+        })?;
+        (raws.remove(0), t)
+    };
+
+    let out = {
+        let mut istft_ws = istft_scratch().lock().expect("iSTFT scratch poisoned");
+        postprocess_demucs_output(
+            raw,
+            t,
+            &mut istft_ws,
+            debug_enabled,
+            perf_enabled,
+            &mut perf,
+        )?
+    };
+
+    if let Some(start) = total_start {
+        perf.total_ns = start.elapsed().as_nanos();
+        log_window_perf(&perf);
+    }
 
-    match run_window_demucs_once(left, right, debug_enabled, perf_enabled) {
+    Ok(out)
+}
+
+/// Run `run` and, if the active execution provider produced near-silent
+/// output, switch the session to CPU and run it once more.
+#[cfg(not(feature = "engine-mock"))]
+fn run_with_runtime_fallback<T>(
+    debug_enabled: bool,
+    mut run: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    match run() {
         Ok(out) => Ok(out),
         Err(e) => {
             let error_text = e.to_string();
@@ -751,7 +844,7 @@ pub fn run_window_demucs(left: &[f32], right: &[f32]) -> Result<Array3<f32>> {
             *session = cpu_session;
             drop(session);
 
-            match run_window_demucs_once(left, right, debug_enabled, perf_enabled) {
+            match run() {
                 Ok(out) => {
                     if debug_enabled {
                         eprintln!("✅ Runtime fallback succeeded: CPU is now active");
@@ -769,76 +862,54 @@ pub fn run_window_demucs(left: &[f32], right: &[f32]) -> Result<Array3<f32>> {
     }
 }
 
+/// Execute the session on `batch` prepared windows stacked in `time_branch`
+/// and `spec_branch`, rejecting near-silent output for audible input.
 #[cfg(not(feature = "engine-mock"))]
-fn run_window_demucs_once(
-    left: &[f32],
-    right: &[f32],
-    debug_enabled: bool,
+fn run_prepared_batch(
+    time_branch: &[f32],
+    spec_branch: &[f32],
+    dims: BatchDims,
     perf_enabled: bool,
-) -> Result<Array3<f32>> {
-    let total_start = perf_enabled.then(Instant::now);
-    let mut perf = WindowPerf::default();
-
-    let raw = {
-        let mut scratch = input_scratch().lock().expect("input scratch poisoned");
-        let (t, f_bins, frames) =
-            prepare_demucs_inputs(left, right, &mut scratch, perf_enabled, &mut perf)?;
-
-        let lock_start = perf_enabled.then(Instant::now);
-        let mut session = SESSION
-            .get()
-            .expect("engine::preload first")
-            .lock()
-            .expect("session poisoned");
-        if let Some(start) = lock_start {
-            perf.lock_wait_ns += start.elapsed().as_nanos();
-        }
-
-        let (out_time, out_freq) = run_demucs_raw_from_inputs(
-            &mut session,
-            io_spec(),
-            t,
-            f_bins,
-            frames,
-            &scratch.time_branch,
-            &scratch.spec_branch,
-            perf_enabled,
-            &mut perf,
-        )?;
-        drop(session);
-        drop(scratch);
-
-        decode_demucs_outputs(
-            out_time,
-            out_freq,
-            t,
-            f_bins,
-            frames,
-            perf_enabled,
-            &mut perf,
-        )?
-    };
+    perf: &mut WindowPerf,
+) -> Result<Vec<DemucsRawOutput>> {
+    let lock_start = perf_enabled.then(Instant::now);
+    let mut session = SESSION
+        .get()
+        .expect("engine::preload first")
+        .lock()
+        .expect("session poisoned");
+    if let Some(start) = lock_start {
+        perf.lock_wait_ns += start.elapsed().as_nanos();
+    }
 
-    let out = postprocess_demucs_output(raw, left, right, debug_enabled, perf_enabled, &mut perf)?;
+    let (out_time, out_freq) = run_demucs_raw_from_inputs(
+        &mut session,
+        io_spec(),
+        dims,
+        time_branch,
+        spec_branch,
+        perf_enabled,
+        perf,
+    )?;
+    drop(session);
 
-    if let Some(start) = total_start {
-        perf.total_ns = start.elapsed().as_nanos();
-        log_window_perf(&perf);
+    let raws = decode_demucs_outputs(out_time, out_freq, dims, perf_enabled, perf)?;
+    for (window, raw) in time_branch.chunks_exact(2 * dims.t).zip(&raws) {
+        let (left, right) = window.split_at(dims.t);
+        ensure_output_is_not_near_silent(left, right, raw)?;
     }
-
-    Ok(out)
+    Ok(raws)
 }
 
 #[cfg(not(feature = "engine-mock"))]
 fn postprocess_demucs_output(
     mut raw: DemucsRawOutput,
-    left: &[f32],
-    right: &[f32],
+    t: usize,
+    istft_ws: &mut IstftBatchWorkspace,
     debug_enabled: bool,
     perf_enabled: bool,
     perf: &mut WindowPerf,
 ) -> Result<Array3<f32>> {
-    let t = left.len();
     let num_sources = raw.num_sources;
 
     if debug_enabled {
@@ -848,8 +919,6 @@ fn postprocess_demucs_output(
         );
     }
 
-    ensure_output_is_not_near_silent(left, right, &raw)?;
-
     let source_specs: Vec<&[f32]> = (0..num_sources)
         .map(|src| {
             let src_freq_offset = src * 4 * DEMUCS_F * DEMUCS_FRAMES;
@@ -858,19 +927,16 @@ fn postprocess_demucs_output(
         .collect();
 
     let istft_start = perf_enabled.then(Instant::now);
-    {
-        let mut istft_ws = istft_scratch().lock().expect("iSTFT scratch poisoned");
-        istft_cac_stereo_sources_add_into(
-            &source_specs,
-            DEMUCS_F,
-            DEMUCS_FRAMES,
-            DEMUCS_NFFT,
-            DEMUCS_HOP,
-            t,
-            &mut istft_ws,
-            &mut raw.data_time,
-        );
-    }
+    istft_cac_stereo_sources_add_into(
+        &source_specs,
+        DEMUCS_F,
+        DEMUCS_FRAMES,
+        DEMUCS_NFFT,
+        DEMUCS_HOP,
+        t,
+        istft_ws,
+        &mut raw.data_time,
+    );
     if let Some(start) = istft_start {
         perf.istft_ns += start.elapsed().as_nanos();
     }
@@ -901,6 +967,334 @@ fn postprocess_demucs_output(
     )?)
 }
 
+/// Windows per `session.run` when the caller does not choose: accelerators
+/// amortize per-call overhead across a few windows, CPU gains little.
+#[cfg(not(feature = "engine-mock"))]
+fn auto_batch_size() -> usize {
+    if RUNTIME_EP_FALLBACK_USED.load(Ordering::SeqCst) {
+        return 1;
+    }
+    match ENGINE_CONTEXT.get().map(|ctx| ctx.selected_kind) {
+        Some(ep::EpKind::Cuda | ep::EpKind::CoreML | ep::EpKind::DirectML) => 4,
+        _ => 1,
+    }
+}
+
+/// Batch size to run with: `requested`, or [`auto_batch_size`], limited to 1
+/// when the model has a fixed batch axis.
+#[cfg(not(feature = "engine-mock"))]
+fn effective_batch_size(requested: Option<usize>) -> usize {
+    if !io_spec().dynamic_batch {
+        if requested.is_some_and(|n| n > 1) && std::env::var("DEBUG_STEMS").is_ok() {
+            eprintln!("ℹ️  Model has a fixed batch axis; running one window per call");
+        }
+        return 1;
+    }
+    requested.unwrap_or_else(auto_batch_size).max(1)
+}
+
+#[cfg(not(feature = "engine-mock"))]
+enum PipelineJob {
+    Window(Vec<f32>, Vec<f32>),
+    /// Run whatever is queued even if it does not fill a batch.
+    Flush,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+enum StagedWindow {
+    Window(Result<Box<PreparedWindow>>),
+    Flush,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+struct PreparedWindow {
+    scratch: InferenceScratch,
+    t: usize,
+    f_bins: usize,
+    frames: usize,
+    perf: WindowPerf,
+    started: Option<Instant>,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+struct ExecutedWindow {
+    raw: DemucsRawOutput,
+    t: usize,
+    perf: WindowPerf,
+    started: Option<Instant>,
+}
+
+/// Stacks prepared windows into `[batch, ...]` inputs and runs them in one
+/// call, returning one result per window in order.
+#[cfg(not(feature = "engine-mock"))]
+struct BatchRunner {
+    debug_enabled: bool,
+    perf_enabled: bool,
+    time_batch: Vec<f32>,
+    spec_batch: Vec<f32>,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+impl BatchRunner {
+    fn run(&mut self, windows: &[PreparedWindow]) -> Vec<Result<ExecutedWindow>> {
+        let dims = BatchDims {
+            batch: windows.len(),
+            t: windows[0].t,
+            f_bins: windows[0].f_bins,
+            frames: windows[0].frames,
+        };
+        let mut perf = WindowPerf::default();
+
+        // A single window runs straight from its own buffers.
+        let (time_branch, spec_branch) = if let [window] = windows {
+            (&window.scratch.time_branch, &window.scratch.spec_branch)
+        } else {
+            self.time_batch.clear();
+            self.spec_batch.clear();
+            for window in windows {
+                self.time_batch
+                    .extend_from_slice(&window.scratch.time_branch);
+                self.spec_batch
+                    .extend_from_slice(&window.scratch.spec_branch);
+            }
+            (&self.time_batch, &self.spec_batch)
+        };
+        let raws = run_with_runtime_fallback(self.debug_enabled, || {
+            run_prepared_batch(time_branch, spec_branch, dims, self.perf_enabled, &mut perf)
+        });
+
+        match raws {
+            Ok(raws) => windows
+                .iter()
+                .zip(raws)
+                .map(|(window, raw)| {
+                    let mut window_perf = window.perf;
+                    window_perf.lock_wait_ns += perf.lock_wait_ns;
+                    window_perf.run_ns += perf.run_ns;
+                    window_perf.extract_ns += perf.extract_ns;
+                    window_perf.decode_ns += perf.decode_ns;
+                    Ok(ExecutedWindow {
+                        raw,
+                        t: dims.t,
+                        perf: window_perf,
+                        started: window.started,
+                    })
+                })
+                .collect(),
+            Err(e) => std::iter::once(Err(e))
+                .chain((1..windows.len()).map(|_| {
+                    Err(anyhow!("Inference failed for an earlier window in the batch").into())
+                }))
+                .collect(),
+        }
+    }
+
+    /// Run and forward every queued window, handing their buffers back to the
+    /// STFT stage. Returns `false` once the next stage has gone away.
+    fn run_queued(
+        &mut self,
+        queued: &mut Vec<PreparedWindow>,
+        executed_tx: &SyncSender<Result<ExecutedWindow>>,
+        recycle_tx: &Sender<InferenceScratch>,
+    ) -> bool {
+        if queued.is_empty() {
+            return true;
+        }
+        let results = self.run(queued);
+        for window in queued.drain(..) {
+            recycle_tx.send(window.scratch).ok();
+        }
+        results
+            .into_iter()
+            .all(|result| executed_tx.send(result).is_ok())
+    }
+}
+
+/// Runs consecutive windows through STFT preparation, model execution and
+/// iSTFT post-processing on three threads, so that while one batch executes,
+/// the next windows' STFT and the previous windows' iSTFT run alongside it.
+///
+/// When the model has a dynamic batch axis, up to
+/// [`WindowPipeline::batch_size`] windows are stacked into each
+/// `session.run`. Outputs come back in submission order. Dropping the
+/// pipeline stops its threads once their current window is done.
+#[cfg(not(feature = "engine-mock"))]
+pub struct WindowPipeline {
+    input: SyncSender<PipelineJob>,
+    output: Receiver<Result<Array3<f32>>>,
+    batch_size: usize,
+}
+
+#[cfg(not(feature = "engine-mock"))]
+impl WindowPipeline {
+    /// Start a pipeline running `batch_size` windows per call, or an automatic
+    /// choice for the active execution provider when `None`.
+    pub fn new(batch_size: Option<usize>) -> Result<Self> {
+        let debug_enabled = std::env::var("DEBUG_STEMS").is_ok();
+        let perf_enabled = perf_config().enabled;
+        let batch_size = effective_batch_size(batch_size);
+        if debug_enabled {
+            eprintln!("ℹ️  Inference batch size: {}", batch_size);
+        }
+
+        let (input, jobs) = mpsc::sync_channel::<PipelineJob>(1);
+        let (staged_tx, staged_rx) = mpsc::sync_channel::<StagedWindow>(batch_size);
+        let (executed_tx, executed_rx) = mpsc::sync_channel::<Result<ExecutedWindow>>(batch_size);
+        let (output_tx, output) = mpsc::sync_channel::<Result<Array3<f32>>>(batch_size);
+        // Input buffers go back to the STFT stage once the session is done
+        // with them, so steady state allocates nothing.
+        let (recycle_tx, recycle_rx) = mpsc::channel::<InferenceScratch>();
+
+        let spawn = |name: &str, body: Box<dyn FnOnce() + Send>| {
+            std::thread::Builder::new()
+                .name(format!("demucs-{}", name))
+                .spawn(body)
+                .map_err(|e| anyhow!("Failed to start {} thread: {}", name, e))
+        };
+
+        spawn(
+            "stft",
+            Box::new(move || {
+                for job in jobs {
+                    let staged = match job {
+                        PipelineJob::Window(left, right) => {
+                            let started = perf_enabled.then(Instant::now);
+                            let mut perf = WindowPerf::default();
+                            let mut scratch = recycle_rx
+                                .try_recv()
+                                .unwrap_or_else(|_| InferenceScratch::with_demucs_capacity());
+                            let prepared = prepare_demucs_inputs(
+                                &left,
+                                &right,
+                                &mut scratch,
+                                perf_enabled,
+                                &mut perf,
+                            )
+                            .map(|(t, f_bins, frames)| {
+                                Box::new(PreparedWindow {
+                                    scratch,
+                                    t,
+                                    f_bins,
+                                    frames,
+                                    perf,
+                                    started,
+                                })
+                            });
+                            StagedWindow::Window(prepared)
+                        }
+                        PipelineJob::Flush => StagedWindow::Flush,
+                    };
+                    if staged_tx.send(staged).is_err() {
+                        break;
+                    }
+                }
+            }),
+        )?;
+
+        spawn(
+            "run",
+            Box::new(move || {
+                let mut runner = BatchRunner {
+                    debug_enabled,
+                    perf_enabled,
+                    time_batch: Vec::new(),
+                    spec_batch: Vec::new(),
+                };
+                let mut queued: Vec<PreparedWindow> = Vec::with_capacity(batch_size);
+                for staged in staged_rx {
+                    let delivered = match staged {
+                        StagedWindow::Window(Ok(window)) => {
+                            queued.push(*window);
+                            queued.len() < batch_size
+                                || runner.run_queued(&mut queued, &executed_tx, &recycle_tx)
+                        }
+                        // Keep outputs in order: run what is queued first.
+                        StagedWindow::Window(Err(e)) => {
+                            runner.run_queued(&mut queued, &executed_tx, &recycle_tx)
+                                && executed_tx.send(Err(e)).is_ok()
+                        }
+                        StagedWindow::Flush => {
+                            runner.run_queued(&mut queued, &executed_tx, &recycle_tx)
+                        }
+                    };
+                    if !delivered {
+                        break;
+                    }
+                }
+            }),
+        )?;
+
+        spawn(
+            "istft",
+            Box::new(move || {
+                let mut istft_ws = IstftBatchWorkspace::default();
+                for executed in executed_rx {
+                    let out = executed.and_then(|mut w| {
+                        let out = postprocess_demucs_output(
+                            w.raw,
+                            w.t,
+                            &mut istft_ws,
+                            debug_enabled,
+                            perf_enabled,
+                            &mut w.perf,
+                        );
+                        if let Some(start) = w.started {
+                            w.perf.total_ns = start.elapsed().as_nanos();
+                            log_window_perf(&w.perf);
+                        }
+                        out
+                    });
+                    if output_tx.send(out).is_err() {
+                        break;
+                    }
+                }
+            }),
+        )?;
+
+        Ok(Self {
+            input,
+            output,
+            batch_size,
+        })
+    }
+
+    /// Windows stacked into each model call.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Windows to keep submitted ahead of [`WindowPipeline::next_output`] so
+    /// every stage stays busy: a batch executing, the next one being
+    /// prepared, and one window in post-processing.
+    pub fn depth(&self) -> usize {
+        2 * self.batch_size + 1
+    }
+
+    /// Queue a window. Blocks while the STFT stage is still busy.
+    pub fn submit(&self, left: Vec<f32>, right: Vec<f32>) -> Result<()> {
+        self.send(PipelineJob::Window(left, right))
+    }
+
+    /// Run the windows queued so far without waiting for a full batch. Call
+    /// once no more windows will be submitted.
+    pub fn flush(&self) -> Result<()> {
+        self.send(PipelineJob::Flush)
+    }
+
+    /// Output of the oldest submitted window still pending.
+    pub fn next_output(&self) -> Result<Array3<f32>> {
+        self.output
+            .recv()
+            .map_err(|_| StemError::from(anyhow!("Inference pipeline stopped")))?
+    }
+
+    fn send(&self, job: PipelineJob) -> Result<()> {
+        self.input
+            .send(job)
+            .map_err(|_| anyhow!("Inference pipeline stopped").into())
+    }
+}
+
 #[cfg(not(feature = "engine-mock"))]
 #[cfg(test)]
 mod runtime_policy_tests {
@@ -986,6 +1380,7 @@ mod runtime_policy_tests {
 mod _engine_mock {
     use super::*;
     use once_cell::sync::OnceCell;
+    use std::{cell::RefCell, collections::VecDeque};
     static MANIFEST: OnceCell<ModelManifest> = OnceCell::new();
 
     pub fn preload(h: &ModelHandle) -> Result<()> {
@@ -999,7 +1394,10 @@ mod _engine_mock {
 
     pub fn run_window_demucs(left: &[f32], right: &[f32]) -> Result<Array3<f32>> {
         let t = left.len().min(right.len());
-        let sources = 4usize;
+        let sources = match MANIFEST.get().map_or(0, |m| m.stems.len()) {
+            0 => 4,
+            n => n,
+        };
         let mut out = vec![0.0f32; sources * 2 * t];
         for s in 0..sources {
             for i in 0..t {
@@ -1010,7 +1408,56 @@ mod _engine_mock {
         }
         Ok(ndarray::Array3::from_shape_vec((sources, 2, t), out)?)
     }
+
+    /// Same interface as the real pipeline. Windows are held until a batch is
+    /// full or flushed, so callers that forget to flush get no output.
+    pub struct WindowPipeline {
+        batch_size: usize,
+        queued: RefCell<Vec<(Vec<f32>, Vec<f32>)>>,
+        outputs: RefCell<VecDeque<Result<Array3<f32>>>>,
+    }
+
+    impl WindowPipeline {
+        pub fn new(batch_size: Option<usize>) -> Result<Self> {
+            Ok(Self {
+                batch_size: batch_size.unwrap_or(1).max(1),
+                queued: RefCell::default(),
+                outputs: RefCell::default(),
+            })
+        }
+
+        pub fn batch_size(&self) -> usize {
+            self.batch_size
+        }
+
+        pub fn depth(&self) -> usize {
+            2 * self.batch_size + 1
+        }
+
+        pub fn submit(&self, left: Vec<f32>, right: Vec<f32>) -> Result<()> {
+            self.queued.borrow_mut().push((left, right));
+            if self.queued.borrow().len() >= self.batch_size {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        pub fn flush(&self) -> Result<()> {
+            let mut outputs = self.outputs.borrow_mut();
+            for (left, right) in self.queued.borrow_mut().drain(..) {
+                outputs.push_back(run_window_demucs(&left, &right));
+            }
+            Ok(())
+        }
+
+        pub fn next_output(&self) -> Result<Array3<f32>> {
+            self.outputs
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or_else(|| Err(anyhow!("No finished window; flush the pipeline").into()))
+        }
+    }
 }
 
 #[cfg(feature = "engine-mock")]
-pub use _engine_mock::{manifest, preload, run_window_demucs};
+pub use _engine_mock::{manifest, preload, run_window_demucs, WindowPipeline};
@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use flacenc::{
+    bitsink::ByteSink,
+    component::{BitRepr, MetadataBlockData, Stream, StreamInfo},
+    config,
+    error::{Verified, Verify},
+    source::{Context, Fill, FrameBuf},
+};
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Streaming FLAC encoder.
+///
+/// Each block of frames is encoded and written as soon as it is complete. The
+/// STREAMINFO header goes out first with placeholder totals and is rewritten
+/// in place with the final sample count and MD5 on [`FlacWriter::finalize`].
+pub struct FlacWriter {
+    file: BufWriter<File>,
+    config: Verified<config::Encoder>,
+    stream_info: StreamInfo,
+    framebuf: FrameBuf,
+    context: Context,
+    pending: Vec<i32>,
+    channels: usize,
+    /// Metadata blocks written after STREAMINFO, as `(block type, body)`.
+    metadata: Vec<(u8, Vec<u8>)>,
+    sink: ByteSink,
+}
+
+impl FlacWriter {
+    pub fn create(
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+        bits_per_sample: u16,
+        metadata: Vec<(u8, Vec<u8>)>,
+    ) -> Result<Self> {
+        let channels = usize::from(channels);
+        let bits_per_sample = usize::from(bits_per_sample);
+        let config = config::Encoder::default()
+            .into_verified()
+            .map_err(|(_, e)| anyhow!("Invalid FLAC encoder config: {}", e))?;
+        let mut stream_info = StreamInfo::new(sample_rate as usize, channels, bits_per_sample)
+            .map_err(|e| anyhow!("Unsupported FLAC stream: {}", e))?;
+        stream_info
+            .set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)
+            .map_err(|e| anyhow!("Invalid FLAC block size: {}", e))?;
+        let framebuf = FrameBuf::with_size(channels, BLOCK_SIZE)
+            .map_err(|e| anyhow!("Unsupported FLAC stream: {}", e))?;
+
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            config,
+            stream_info,
+            framebuf,
+            context: Context::new(bits_per_sample, channels),
+            pending: Vec::with_capacity(BLOCK_SIZE * channels),
+            channels,
+            metadata,
+            sink: ByteSink::new(),
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /// Append interleaved samples already quantized to the stream bit depth.
+    pub fn write_samples(&mut self, samples: &[i32]) -> Result<()> {
+        let block_len = BLOCK_SIZE * self.channels;
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (block_len - self.pending.len()).min(rest.len());
+            self.pending.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.pending.len() == block_len {
+                self.encode_pending()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.encode_pending()?;
+        }
+        self.stream_info.set_md5_digest(&self.context.md5_digest());
+        // Per the spec the block size bounds exclude the short final block, and
+        // decoders only accept frame-numbered streams when min == max.
+        self.stream_info
+            .set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)
+            .map_err(|e| anyhow!("Invalid FLAC block size: {}", e))?;
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut stream = Stream::with_stream_info(self.stream_info.clone());
+        for (block_type, body) in &self.metadata {
+            stream.add_metadata_block(
+                MetadataBlockData::new_unknown(*block_type, body)
+                    .map_err(|e| anyhow!("Invalid FLAC metadata block: {}", e))?,
+            );
+        }
+        self.sink.clear();
+        stream
+            .write(&mut self.sink)
+            .map_err(|e| anyhow!("Failed to write FLAC header: {}", e))?;
+        self.file.write_all(self.sink.as_slice())?;
+        Ok(())
+    }
+
+    fn encode_pending(&mut self) -> Result<()> {
+        self.framebuf
+            .fill_interleaved(&self.pending)
+            .map_err(|e| anyhow!("Failed to buffer FLAC frame: {}", e))?;
+        self.context
+            .fill_interleaved(&self.pending)
+            .map_err(|e| anyhow!("Failed to buffer FLAC frame: {}", e))?;
+        self.pending.clear();
+
+        let frame_number = self
+            .context
+            .current_frame_number()
+            .expect("context filled above");
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.config,
+            &self.framebuf,
+            frame_number,
+            &self.stream_info,
+        )
+        .map_err(|e| anyhow!("Failed to encode FLAC frame: {}", e))?;
+        self.stream_info.update_frame_info(&frame);
+
+        self.sink.clear();
+        frame
+            .write(&mut self.sink)
+            .map_err(|e| anyhow!("Failed to write FLAC frame: {}", e))?;
+        self.file.write_all(self.sink.as_slice())?;
+        Ok(())
+    }
+}
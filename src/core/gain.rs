@@ -0,0 +1,188 @@
+//! Gain staging for written stems: static gains chosen from measured levels,
+//! and a lookahead limiter.
+
+use crate::{
+    error::Result,
+    types::{GainStaging, StemStats},
+};
+
+use std::collections::VecDeque;
+
+/// How far ahead the limiter looks for peaks; gain reductions ramp in over
+/// this long.
+const LIMITER_LOOKAHEAD_SECS: f64 = 0.002;
+/// Time constant of the limiter's gain recovery.
+const LIMITER_RELEASE_SECS: f64 = 0.1;
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// Reject targets that are not a finite number of dB.
+pub(crate) fn validate(mode: GainStaging) -> Result<()> {
+    let target = match mode {
+        GainStaging::Off => return Ok(()),
+        GainStaging::Common { ceiling_db } | GainStaging::Limit { ceiling_db } => ceiling_db,
+        GainStaging::NormalizePeak { peak_db } => peak_db,
+        GainStaging::NormalizeLoudness { lufs } => lufs,
+    };
+    if target.is_finite() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("gain target must be a finite level in dB, got {}", target).into())
+    }
+}
+
+/// Linear gain for each stem under `mode`, from the levels measured on the
+/// stems before any gain.
+pub fn stem_gains(mode: GainStaging, levels: &[StemStats]) -> Vec<f32> {
+    // Silent stems keep unity gain rather than being scaled by infinity.
+    let towards = |target: f32, level: f32| {
+        if level > 0.0 {
+            target / level
+        } else {
+            1.0
+        }
+    };
+
+    match mode {
+        GainStaging::Off | GainStaging::Limit { .. } => vec![1.0; levels.len()],
+        GainStaging::Common { ceiling_db } => {
+            let loudest = levels.iter().map(|l| l.true_peak).fold(0.0, f32::max);
+            let gain = towards(db_to_gain(ceiling_db), loudest).min(1.0);
+            vec![gain; levels.len()]
+        }
+        GainStaging::NormalizePeak { peak_db } => levels
+            .iter()
+            .map(|l| towards(db_to_gain(peak_db), l.true_peak))
+            .collect(),
+        GainStaging::NormalizeLoudness { lufs } => levels
+            .iter()
+            .map(|l| {
+                if l.integrated_lufs.is_finite() {
+                    db_to_gain(lufs - l.integrated_lufs).min(towards(1.0, l.true_peak))
+                } else {
+                    1.0
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Channel-linked lookahead peak limiter.
+///
+/// The gain needed for each frame is known [`LIMITER_LOOKAHEAD_SECS`] before
+/// the frame is output, and reductions are ramped in over that time, so no
+/// output sample exceeds the ceiling and the limiter never hard-clips. Gain
+/// recovers smoothly afterwards. The lookahead delay is compensated: the
+/// output has exactly as many frames as the input once flushed.
+pub struct Limiter {
+    ceiling: f32,
+    lookahead: usize,
+    release: f32,
+    channels: usize,
+    /// Interleaved frames waiting to be output.
+    delay: VecDeque<f32>,
+    /// Increasing candidates for the minimum required gain over the
+    /// lookahead, as (frame index, gain).
+    minima: VecDeque<(u64, f32)>,
+    frame: u64,
+    smoothed: f32,
+    /// Smoothed gains being averaged into the attack ramp.
+    ramp: VecDeque<f32>,
+    ramp_sum: f64,
+}
+
+impl Limiter {
+    /// A limiter for stereo audio.
+    pub fn new(sample_rate: u32, ceiling_db: f32) -> Self {
+        Self::with_channels(sample_rate, 2, ceiling_db)
+    }
+
+    /// A limiter applying one gain to all `channels` of each frame.
+    pub fn with_channels(sample_rate: u32, channels: u16, ceiling_db: f32) -> Self {
+        let lookahead = ((sample_rate as f64 * LIMITER_LOOKAHEAD_SECS).round() as usize).max(1);
+        let channels = usize::from(channels.max(1));
+        Self {
+            ceiling: db_to_gain(ceiling_db),
+            lookahead,
+            release: (1.0 - (-1.0 / (LIMITER_RELEASE_SECS * sample_rate as f64)).exp()) as f32,
+            channels,
+            delay: VecDeque::with_capacity((lookahead + 1) * channels),
+            minima: VecDeque::new(),
+            frame: 0,
+            smoothed: 1.0,
+            ramp: VecDeque::with_capacity(lookahead + 1),
+            ramp_sum: 0.0,
+        }
+    }
+
+    /// Limit interleaved `input`, appending the frames that leave the
+    /// lookahead delay to `out`.
+    pub fn process_interleaved(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        for frame in input.chunks_exact(self.channels) {
+            self.push(frame, out);
+        }
+    }
+
+    /// Output the frames still in the lookahead delay.
+    pub fn flush(&mut self, out: &mut Vec<f32>) {
+        let silence = vec![0.0; self.channels];
+        for _ in 0..self.delay.len() / self.channels {
+            self.push(&silence, out);
+        }
+        self.delay.clear();
+    }
+
+    fn push(&mut self, frame: &[f32], out: &mut Vec<f32>) {
+        let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // Minimum required gain over the last `lookahead + 1` frames.
+        while self.minima.back().is_some_and(|&(_, g)| g >= required) {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((self.frame, required));
+        while self
+            .minima
+            .front()
+            .is_some_and(|&(idx, _)| idx + (self.lookahead as u64) < self.frame)
+        {
+            self.minima.pop_front();
+        }
+        let target = self.minima.front().map_or(1.0, |&(_, g)| g);
+        self.frame += 1;
+
+        self.smoothed = if target < self.smoothed {
+            target
+        } else {
+            self.smoothed + (target - self.smoothed) * self.release
+        };
+
+        // Averaging over the lookahead turns steps into ramps that still reach
+        // the required gain by the time the loud frame is output.
+        self.ramp.push_back(self.smoothed);
+        self.ramp_sum += self.smoothed as f64;
+        if self.ramp.len() > self.lookahead + 1 {
+            self.ramp_sum -= self.ramp.pop_front().unwrap_or(0.0) as f64;
+        }
+        let gain = (self.ramp_sum / self.ramp.len() as f64) as f32;
+
+        // The ramp keeps every frame under the ceiling; clamping only absorbs
+        // rounding in the running average.
+        self.delay.extend(frame);
+        if self.delay.len() > self.lookahead * self.channels {
+            for sample in self.delay.drain(..self.channels) {
+                out.push((sample * gain).clamp(-self.ceiling, self.ceiling));
+            }
+        }
+    }
+}
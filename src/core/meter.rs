@@ -0,0 +1,225 @@
+//! Streaming level meter for written stems: sample peak, true peak, RMS and
+//! EBU R128 integrated loudness.
+
+use crate::types::StemStats;
+
+use std::f64::consts::PI;
+
+/// Taps on each side of an interpolated point for true-peak oversampling.
+const TRUE_PEAK_HALF_TAPS: usize = 6;
+const TRUE_PEAK_TAPS: usize = 2 * TRUE_PEAK_HALF_TAPS;
+/// Oversampling factor for true-peak measurement (ITU-R BS.1770-4, Annex 2).
+const TRUE_PEAK_OVERSAMPLE: usize = 4;
+
+/// Gating block length and step of BS.1770 integrated loudness, in 100 ms
+/// sub-blocks.
+const LOUDNESS_BLOCK_SUBBLOCKS: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Direct form I biquad with `a0` normalized to 1.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two-stage K-weighting filter of BS.1770 (high shelf, then high pass),
+/// designed for `sample_rate`.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Hann-windowed sinc taps interpolating `phase / TRUE_PEAK_OVERSAMPLE` of the
+/// way between the two middle samples of a [`TRUE_PEAK_TAPS`]-sample history.
+fn true_peak_taps() -> Vec<[f32; TRUE_PEAK_TAPS]> {
+    (1..TRUE_PEAK_OVERSAMPLE)
+        .map(|phase| {
+            let frac = phase as f64 / TRUE_PEAK_OVERSAMPLE as f64;
+            let mut taps = [0f32; TRUE_PEAK_TAPS];
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let d = frac - (k as f64 - (TRUE_PEAK_HALF_TAPS - 1) as f64);
+                let sinc = (PI * d).sin() / (PI * d);
+                let window = 0.5 * (1.0 + (PI * d / TRUE_PEAK_HALF_TAPS as f64).cos());
+                *tap = (sinc * window) as f32;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// Accumulates [`StemStats`] over interleaved samples fed in any block size.
+pub struct Meter {
+    channels: usize,
+    peak: f32,
+    true_peak: f32,
+    sum_squares: f64,
+    samples: u64,
+    /// Last [`TRUE_PEAK_TAPS`] samples of every channel.
+    history: Vec<[f32; TRUE_PEAK_TAPS]>,
+    taps: Vec<[f32; TRUE_PEAK_TAPS]>,
+    filters: Vec<[Biquad; 2]>,
+    /// BS.1770 weight of every channel's K-weighted energy.
+    weights: Vec<f64>,
+    subblock_len: usize,
+    subblock_fill: usize,
+    subblock_energy: f64,
+    /// K-weighted energy of every complete 100 ms sub-block, weighted and
+    /// summed over channels.
+    subblocks: Vec<f64>,
+}
+
+impl Meter {
+    /// Meter for `channels` channels that all count fully towards loudness,
+    /// as mono and stereo channels do.
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self::with_channel_weights(sample_rate, &vec![1.0; usize::from(channels.max(1))])
+    }
+
+    /// Meter for one channel per entry of `weights`, the BS.1770 weight of
+    /// that channel in integrated loudness: 1.0 for front channels, 1.41 for
+    /// surrounds and 0.0 for the LFE.
+    pub fn with_channel_weights(sample_rate: u32, weights: &[f32]) -> Self {
+        let weights: Vec<f64> = if weights.is_empty() {
+            vec![1.0]
+        } else {
+            weights.iter().map(|&w| f64::from(w)).collect()
+        };
+        let channels = weights.len();
+        Self {
+            channels,
+            peak: 0.0,
+            true_peak: 0.0,
+            sum_squares: 0.0,
+            samples: 0,
+            history: vec![[0.0; TRUE_PEAK_TAPS]; channels],
+            taps: true_peak_taps(),
+            filters: vec![k_weighting(sample_rate); channels],
+            weights,
+            subblock_len: ((sample_rate as f64 / 10.0).round() as usize).max(1),
+            subblock_fill: 0,
+            subblock_energy: 0.0,
+            subblocks: Vec::new(),
+        }
+    }
+
+    pub fn process_interleaved(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (ch, &sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                self.sum_squares += sample as f64 * sample as f64;
+                self.push_true_peak(ch, sample);
+
+                let [shelf, high_pass] = &mut self.filters[ch];
+                let weighted = high_pass.process(shelf.process(sample as f64));
+                self.subblock_energy += self.weights[ch] * weighted * weighted;
+            }
+            self.samples += self.channels as u64;
+
+            self.subblock_fill += 1;
+            if self.subblock_fill == self.subblock_len {
+                self.subblocks.push(self.subblock_energy);
+                self.subblock_fill = 0;
+                self.subblock_energy = 0.0;
+            }
+        }
+    }
+
+    fn push_true_peak(&mut self, ch: usize, sample: f32) {
+        let history = &mut self.history[ch];
+        history.copy_within(1.., 0);
+        history[TRUE_PEAK_TAPS - 1] = sample;
+        for taps in &self.taps {
+            let interpolated: f32 = history.iter().zip(taps).map(|(x, h)| x * h).sum();
+            self.true_peak = self.true_peak.max(interpolated.abs());
+        }
+    }
+
+    /// Statistics of everything processed so far. `clipped_samples` is left at
+    /// zero; clipping happens, and is counted, in the writer.
+    pub fn finish(mut self) -> StemStats {
+        // Flush the samples still waiting for their right-hand interpolation
+        // taps.
+        for ch in 0..self.channels {
+            for _ in 0..TRUE_PEAK_HALF_TAPS {
+                self.push_true_peak(ch, 0.0);
+            }
+        }
+
+        StemStats {
+            sample_peak: self.peak,
+            true_peak: self.true_peak.max(self.peak),
+            rms: if self.samples > 0 {
+                (self.sum_squares / self.samples as f64).sqrt() as f32
+            } else {
+                0.0
+            },
+            integrated_lufs: self.integrated_loudness() as f32,
+            clipped_samples: 0,
+        }
+    }
+
+    /// Gated integrated loudness (BS.1770-4) over 400 ms blocks with 75%
+    /// overlap; `-inf` if no block passes the gates.
+    fn integrated_loudness(&self) -> f64 {
+        let block_samples = (LOUDNESS_BLOCK_SUBBLOCKS * self.subblock_len) as f64;
+        let blocks: Vec<f64> = self
+            .subblocks
+            .windows(LOUDNESS_BLOCK_SUBBLOCKS)
+            .map(|subs| subs.iter().sum::<f64>() / block_samples)
+            .collect();
+        let loudness = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let gated_mean = |gate: f64| {
+            let kept: Vec<f64> = blocks
+                .iter()
+                .copied()
+                .filter(|&energy| loudness(energy) > gate)
+                .collect();
+            (!kept.is_empty()).then(|| kept.iter().sum::<f64>() / kept.len() as f64)
+        };
+
+        let Some(absolute) = gated_mean(ABSOLUTE_GATE_LUFS) else {
+            return f64::NEG_INFINITY;
+        };
+        gated_mean(loudness(absolute) + RELATIVE_GATE_LU).map_or(f64::NEG_INFINITY, loudness)
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use rubato::{InterpolationParameters, InterpolationType, Resampler, SincFixedIn, WindowFunction};
+
+use crate::{
+    core::tags,
+    types::{AudioData, AudioMetadata},
+};
+
+const CHUNK_FRAMES: usize = 1024;
+
+fn sinc_parameters() -> InterpolationParameters {
+    InterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        oversampling_factor: 256,
+        interpolation: InterpolationType::Cubic,
+        window: WindowFunction::BlackmanHarris2,
+    }
+}
+
+/// Streaming sinc resampler working on interleaved frames.
+///
+/// Input can be pushed in arbitrary block sizes; output is appended as soon as
+/// full resampler chunks are available. Call [`StreamResampler::flush`] once all
+/// input has been pushed to drain the filter tail.
+pub struct StreamResampler {
+    inner: SincFixedIn<f32>,
+    channels: usize,
+    ratio: f64,
+    pending: Vec<Vec<f32>>,
+    consumed_frames: usize,
+    emitted_frames: usize,
+}
+
+impl StreamResampler {
+    pub fn new(from_rate: u32, to_rate: u32, channels: usize) -> Result<Self> {
+        if from_rate == 0 || to_rate == 0 {
+            return Err(anyhow!(
+                "Invalid sample rates for resampling: {} -> {}",
+                from_rate,
+                to_rate
+            ));
+        }
+        if channels == 0 {
+            return Err(anyhow!("Cannot resample audio with zero channels"));
+        }
+
+        let ratio = to_rate as f64 / from_rate as f64;
+        let inner = SincFixedIn::<f32>::new(ratio, 1.0, sinc_parameters(), CHUNK_FRAMES, channels)?;
+
+        Ok(Self {
+            inner,
+            channels,
+            ratio,
+            pending: vec![Vec::with_capacity(CHUNK_FRAMES); channels],
+            consumed_frames: 0,
+            emitted_frames: 0,
+        })
+    }
+
+    /// Number of output frames that correspond to all input pushed so far.
+    pub fn expected_output_frames(&self) -> usize {
+        (self.consumed_frames as f64 * self.ratio).round() as usize
+    }
+
+    pub fn process_interleaved(&mut self, input: &[f32], out: &mut Vec<f32>) -> Result<()> {
+        if !input.len().is_multiple_of(self.channels) {
+            return Err(anyhow!(
+                "Interleaved block of {} samples is not a multiple of {} channels",
+                input.len(),
+                self.channels
+            ));
+        }
+
+        for frame in input.chunks_exact(self.channels) {
+            for (ch, sample) in frame.iter().enumerate() {
+                self.pending[ch].push(*sample);
+            }
+            self.consumed_frames += 1;
+
+            if self.pending[0].len() == CHUNK_FRAMES {
+                self.run_chunk(out, usize::MAX)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drain the filter tail until `total_frames` output frames have been
+    /// emitted overall. The tail is cut at `total_frames` and padded with
+    /// silence if the resampler runs dry first.
+    pub fn flush(&mut self, total_frames: usize, out: &mut Vec<f32>) -> Result<()> {
+        while self.emitted_frames < total_frames {
+            let before = self.emitted_frames;
+            for channel in &mut self.pending {
+                channel.resize(CHUNK_FRAMES, 0.0);
+            }
+            self.run_chunk(out, total_frames)?;
+            if self.emitted_frames == before {
+                break;
+            }
+        }
+
+        if self.emitted_frames < total_frames {
+            let missing = total_frames - self.emitted_frames;
+            out.resize(out.len() + missing * self.channels, 0.0);
+            self.emitted_frames = total_frames;
+        }
+
+        Ok(())
+    }
+
+    fn run_chunk(&mut self, out: &mut Vec<f32>, limit: usize) -> Result<()> {
+        let resampled = self.inner.process(&self.pending, None)?;
+        for channel in &mut self.pending {
+            channel.clear();
+        }
+
+        let produced = resampled[0].len();
+        let take = produced.min(limit.saturating_sub(self.emitted_frames));
+        out.reserve(take * self.channels);
+        for i in 0..take {
+            for channel in &resampled {
+                out.push(channel[i]);
+            }
+        }
+        self.emitted_frames += take;
+
+        Ok(())
+    }
+}
+
+/// Resample interleaved samples in one go, keeping the duration of the input.
+pub fn resample_interleaved(
+    samples: &[f32],
+    channels: u16,
+    from_rate: u32,
+    to_rate: u32,
+) -> Result<Vec<f32>> {
+    let channels = usize::from(channels.max(1));
+    if from_rate == to_rate {
+        return Ok(samples.to_vec());
+    }
+
+    let mut resampler = StreamResampler::new(from_rate, to_rate, channels)?;
+    let mut out = Vec::with_capacity(
+        (samples.len() as f64 * to_rate as f64 / from_rate as f64) as usize + channels,
+    );
+    resampler.process_interleaved(samples, &mut out)?;
+    let total_frames = resampler.expected_output_frames();
+    resampler.flush(total_frames, &mut out)?;
+
+    Ok(out)
+}
+
+pub fn resample_audio(audio: &AudioData, to_rate: u32) -> Result<AudioData> {
+    Ok(AudioData {
+        samples: resample_interleaved(&audio.samples, audio.channels, audio.sample_rate, to_rate)?,
+        sample_rate: to_rate,
+        channels: audio.channels,
+        metadata: AudioMetadata {
+            time_reference: audio
+                .metadata
+                .time_reference
+                .map(|samples| tags::rescale(samples, audio.sample_rate, to_rate)),
+            ..audio.metadata.clone()
+        },
+        damage: audio.damage,
+    })
+}
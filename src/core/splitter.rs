@@ -1,126 +1,1510 @@
 use crate::{
     core::{
-        audio::{create_wav_writer, read_audio, sample_to_i16, WavWriter},
+        audio::{AudioReader, AudioWriter},
+        checkpoint::{f32s_from_le, Checkpoint, Identity},
+        dsp::{mix_consistency, transition_weights, wiener_filter},
         engine,
+        gain::{self, gain_to_db, Limiter},
+        meter::Meter,
+        resample::StreamResampler,
+        tags,
     },
-    error::Result,
+    error::{Result, StemError},
     io::progress::{emit_split_progress, SplitProgress},
-    model::model_manager::ensure_model,
-    types::{SplitOptions, SplitResult},
+    model::model_manager::ensure_model_cancellable,
+    types::{
+        AudioData, AudioMetadata, BatchItem, BatchResult, ComplementMethod, DecodeDamage,
+        GainStaging, MixConsistency, MixResidual, ModelManifest, MultichannelMode, OutputFormat,
+        SplitOptions, SplitResult, StemBuffers, StemFile, StemStats, TrackSelector,
+    },
 };
 
+use indexmap::IndexMap;
+
+use ndarray::{s, Array3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashSet, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
 };
 
-struct StemOutput {
-    stem_idx: usize,
+/// Stem order of the 4-source htdemucs models, used when the manifest does not
+/// name its sources.
+const DEFAULT_STEMS: [&str; 4] = ["vocals", "drums", "bass", "other"];
+
+/// STFT size and hop of the Wiener refinement, those of the htdemucs
+/// spectrogram branch.
+const WIENER_NFFT: usize = 4096;
+const WIENER_HOP: usize = 1024;
+
+/// -3 dB, the ITU-R BS.775 downmix gain of the centre and surround channels.
+const DOWNMIX_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Where the samples of a written stem come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StemSource {
+    /// One of the model's sources.
+    Model(usize),
+    /// Sum of every model source except this one.
+    Others(usize),
+    /// The input mixture minus this model source.
+    MixMinus(usize),
+}
+
+/// Destination for the interleaved samples of one stem.
+trait StemSink {
+    type Output;
+
+    fn write(&mut self, samples: &[f32]) -> Result<()>;
+    /// Interleaved channels the sink expects.
+    fn channels(&self) -> usize;
+    /// Samples clamped to full scale by the sink so far.
+    fn clipped_samples(&self) -> u64;
+    /// Somewhere to hold samples back while gain staging measures them.
+    fn spool(&self) -> Result<Spool>;
+    fn finish(self) -> Result<Self::Output>;
+}
+
+struct FileSink {
+    path: String,
+    writer: AudioWriter,
+    channels: u16,
+}
+
+impl StemSink for FileSink {
+    type Output = String;
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        Ok(self.writer.write_samples(samples)?)
+    }
+
+    fn channels(&self) -> usize {
+        usize::from(self.channels)
+    }
+
+    fn clipped_samples(&self) -> u64 {
+        self.writer.clipped_samples()
+    }
+
+    fn spool(&self) -> Result<Spool> {
+        let dir = Path::new(&self.path).parent().unwrap_or(Path::new("."));
+        Ok(Spool::File {
+            writer: BufWriter::new(tempfile::tempfile_in(dir)?),
+            samples: 0,
+        })
+    }
+
+    fn finish(self) -> Result<String> {
+        self.writer.finalize()?;
+        Ok(self.path)
+    }
+}
+
+struct MemorySink {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+    metadata: AudioMetadata,
+}
+
+impl StemSink for MemorySink {
+    type Output = AudioData;
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.samples.extend_from_slice(samples);
+        Ok(())
+    }
+
+    fn channels(&self) -> usize {
+        usize::from(self.channels)
+    }
+
+    fn clipped_samples(&self) -> u64 {
+        0
+    }
+
+    fn spool(&self) -> Result<Spool> {
+        Ok(Spool::Memory(Vec::new()))
+    }
+
+    fn finish(self) -> Result<AudioData> {
+        Ok(AudioData {
+            samples: self.samples,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            metadata: self.metadata,
+            damage: DecodeDamage::default(),
+        })
+    }
+}
+
+/// Output frames of the decoded span that fall inside the requested range.
+#[derive(Clone, Copy, Debug, Default)]
+struct Trim {
+    /// Leading context frames to drop.
+    skip: usize,
+    /// Frames to keep after `skip`; `None` keeps everything.
+    keep: Option<usize>,
+}
+
+/// Samples of a stem held back until gain staging knows every stem's level.
+enum Spool {
+    Memory(Vec<f32>),
+    /// An unnamed temporary file next to the stem, removed once closed.
+    File {
+        writer: BufWriter<File>,
+        samples: usize,
+    },
+}
+
+impl Spool {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        match self {
+            Spool::Memory(spooled) => spooled.extend_from_slice(samples),
+            Spool::File {
+                writer,
+                samples: spooled,
+            } => {
+                for sample in samples {
+                    writer.write_all(&sample.to_le_bytes())?;
+                }
+                *spooled += samples.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Feed everything spooled to `f`, in blocks of whole `channels`-channel
+    /// frames.
+    fn replay(self, channels: usize, mut f: impl FnMut(&[f32]) -> Result<()>) -> Result<()> {
+        let block_samples = READ_BLOCK_FRAMES * channels;
+        match self {
+            Spool::Memory(spooled) => spooled.chunks(block_samples).try_for_each(f),
+            Spool::File { writer, samples } => {
+                let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                let mut reader = BufReader::new(file);
+                let mut bytes = vec![0u8; block_samples * 4];
+                let mut remaining = samples;
+                while remaining > 0 {
+                    let block = remaining.min(block_samples);
+                    reader.read_exact(&mut bytes[..block * 4])?;
+                    f(&f32s_from_le(&bytes[..block * 4])?)?;
+                    remaining -= block;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What happens to a stem's samples between trimming and its sink.
+enum GainStage {
+    Direct,
+    Limit(Limiter),
+    /// Held back until the static gain of every stem is known.
+    Spool(Spool),
+}
+
+struct StemOutput<S> {
+    source: StemSource,
+    stem_name: String,
+    sink: S,
+    channels: usize,
+    resampler: Option<StreamResampler>,
+    trim: Trim,
+    /// Output frames produced so far, before trimming.
+    produced: usize,
+    meter: Meter,
+    stage: GainStage,
+    limited: Vec<f32>,
+}
+
+/// A stem with all of its samples produced, waiting for its gain.
+struct FlushedStem<S> {
     stem_name: String,
-    writer: WavWriter,
+    sink: S,
+    channels: usize,
+    spool: Option<Spool>,
+    /// Levels before any static gain.
+    levels: StemStats,
+}
+
+struct FinishedStem<T> {
+    output: T,
+    stats: StemStats,
+    gain_db: f32,
+}
+
+impl<S: StemSink> FlushedStem<S> {
+    fn finish(mut self, gain: f32) -> Result<FinishedStem<S::Output>> {
+        if let Some(spool) = self.spool.take() {
+            let sink = &mut self.sink;
+            let mut scaled = Vec::new();
+            spool.replay(self.channels, |samples| {
+                scaled.clear();
+                scaled.extend(samples.iter().map(|s| s * gain));
+                sink.write(&scaled)
+            })?;
+        }
+
+        // A static gain scales every level by the same amount.
+        let levels = self.levels;
+        let gain_db = gain_to_db(gain);
+        let stats = StemStats {
+            sample_peak: levels.sample_peak * gain,
+            true_peak: levels.true_peak * gain,
+            rms: levels.rms * gain,
+            integrated_lufs: levels.integrated_lufs + gain_db,
+            clipped_samples: self.sink.clipped_samples(),
+        };
+        Ok(FinishedStem {
+            output: self.sink.finish()?,
+            stats,
+            gain_db,
+        })
+    }
+}
+
+impl<S: StemSink> StemOutput<S> {
+    fn write_interleaved(&mut self, frames: &[f32], scratch: &mut Vec<f32>) -> Result<()> {
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                scratch.clear();
+                resampler.process_interleaved(frames, scratch)?;
+                self.write_trimmed(scratch)
+            }
+            None => self.write_trimmed(frames),
+        }
+    }
+
+    fn flush(mut self, total_frames: usize, scratch: &mut Vec<f32>) -> Result<FlushedStem<S>> {
+        if let Some(resampler) = self.resampler.as_mut() {
+            scratch.clear();
+            resampler.flush(total_frames, scratch)?;
+            self.write_trimmed(scratch)?;
+        }
+        if let GainStage::Limit(limiter) = &mut self.stage {
+            self.limited.clear();
+            limiter.flush(&mut self.limited);
+            self.meter.process_interleaved(&self.limited);
+            self.sink.write(&self.limited)?;
+        }
+
+        Ok(FlushedStem {
+            stem_name: self.stem_name,
+            sink: self.sink,
+            channels: self.channels,
+            spool: match self.stage {
+                GainStage::Spool(spool) => Some(spool),
+                GainStage::Direct | GainStage::Limit(_) => None,
+            },
+            levels: self.meter.finish(),
+        })
+    }
+
+    fn write_trimmed(&mut self, samples: &[f32]) -> Result<()> {
+        let frames = samples.len() / self.channels;
+        let start = self.trim.skip.saturating_sub(self.produced).min(frames);
+        let end = self.trim.keep.map_or(frames, |keep| {
+            (self.trim.skip + keep)
+                .saturating_sub(self.produced)
+                .min(frames)
+        });
+        self.produced += frames;
+        if end > start {
+            self.emit(&samples[start * self.channels..end * self.channels])?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, samples: &[f32]) -> Result<()> {
+        match &mut self.stage {
+            GainStage::Direct => {
+                self.meter.process_interleaved(samples);
+                self.sink.write(samples)
+            }
+            GainStage::Limit(limiter) => {
+                self.limited.clear();
+                limiter.process_interleaved(samples, &mut self.limited);
+                self.meter.process_interleaved(&self.limited);
+                self.sink.write(&self.limited)
+            }
+            GainStage::Spool(spool) => {
+                self.meter.process_interleaved(samples);
+                spool.write(samples)
+            }
+        }
+    }
+}
+
+/// Energy of the input mixture and of the mixture minus the summed model
+/// sources, over the requested range at the model rate.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct MixEnergy {
+    mix: f64,
+    residual: f64,
+    samples: u64,
+}
+
+impl MixEnergy {
+    /// Add interleaved stereo `residual` frames of model input `pair`,
+    /// starting at input frame `start_frame`.
+    fn add(&mut self, input: &InputWindow<'_>, pair: usize, start_frame: usize, residual: &[f32]) {
+        for (i, frame) in residual.chunks_exact(2).enumerate() {
+            let (left, right) = input.pair_frame(pair, (start_frame + i) as isize);
+            self.mix += (left * left + right * right) as f64;
+            self.residual += (frame[0] * frame[0] + frame[1] * frame[1]) as f64;
+        }
+        self.samples += residual.len() as u64;
+    }
+
+    fn residual(&self) -> MixResidual {
+        MixResidual {
+            rms: if self.samples > 0 {
+                (self.residual / self.samples as f64).sqrt() as f32
+            } else {
+                0.0
+            },
+            relative_db: if self.residual > 0.0 {
+                (10.0 * (self.residual / self.mix).log10()) as f32
+            } else {
+                f32::NEG_INFINITY
+            },
+        }
+    }
+}
+
+/// Inference windows of a split so far, and how many of them were skipped
+/// because their input was silent.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct WindowCounts {
+    total: usize,
+    silent: usize,
+}
+
+/// Finished stems of a split with their levels.
+struct SplitStems<T> {
+    stems: IndexMap<String, FinishedStem<T>>,
+    mix_residual: MixResidual,
+    windows: WindowCounts,
+    decode_damage: DecodeDamage,
+}
+
+/// Weighted overlap-add accumulator for consecutive inference windows.
+///
+/// The buffer is aligned with the start of the current inference step. Windows
+/// are added at an offset within `[0, capacity - win]` (non-zero when shifted
+/// for test-time augmentation) and finished frames are drained from the front
+/// before the next step.
+struct OverlapAdd {
+    weights: Vec<f32>,
+    acc: Vec<f32>,
+    weight_sum: Vec<f32>,
+    stems: usize,
+    capacity: usize,
+}
+
+/// Input augmentation undone on the model output before averaging.
+#[derive(Clone, Copy)]
+struct Augment {
+    invert: bool,
+    swap: bool,
 }
 
-fn audio_frame_count(samples: &[f32], channels: u16) -> usize {
-    let channels = usize::from(channels.max(1));
-    samples.len() / channels
+/// A window submitted to the model whose output has not been collected yet.
+struct PendingWindow {
+    /// Model input pair of [`ChannelMap`] the window was taken from.
+    pair: usize,
+    offset: usize,
+    augment: Augment,
+    /// The augmented input window, kept when the sources are refined against
+    /// it.
+    mix: Option<(Vec<f32>, Vec<f32>)>,
+    /// Index among the windows handed to the model, or `None` for a silent
+    /// window that skipped it.
+    model_window: Option<usize>,
 }
 
-fn fill_stereo_window(
-    samples: &[f32],
+impl Augment {
+    fn variants(flip_polarity: bool, flip_channels: bool) -> Vec<Augment> {
+        let mut out = Vec::with_capacity(4);
+        for invert in [false, true] {
+            for swap in [false, true] {
+                if (invert && !flip_polarity) || (swap && !flip_channels) {
+                    continue;
+                }
+                out.push(Augment { invert, swap });
+            }
+        }
+        out
+    }
+
+    fn apply(self, left: &mut [f32], right: &mut [f32]) {
+        if self.swap {
+            left.swap_with_slice(right);
+        }
+        if self.invert {
+            left.iter_mut().for_each(|x| *x = -*x);
+            right.iter_mut().for_each(|x| *x = -*x);
+        }
+    }
+}
+
+impl OverlapAdd {
+    fn new(weights: Vec<f32>, stems: usize, max_offset: usize) -> Self {
+        let capacity = weights.len() + max_offset;
+        Self {
+            weights,
+            acc: vec![0.0; stems * 2 * capacity],
+            weight_sum: vec![0.0; capacity],
+            stems,
+            capacity,
+        }
+    }
+
+    fn add_window(&mut self, out: &Array3<f32>, offset: usize, augment: Augment) {
+        let t = out.shape()[2].min(self.weights.len());
+        let sign = if augment.invert { -1.0 } else { 1.0 };
+        for s in 0..self.stems {
+            for ch in 0..2 {
+                let src_ch = if augment.swap { 1 - ch } else { ch };
+                let base = (s * 2 + ch) * self.capacity + offset;
+                for i in 0..t {
+                    self.acc[base + i] += sign * out[(s, src_ch, i)] * self.weights[i];
+                }
+            }
+        }
+        for i in 0..t {
+            self.weight_sum[offset + i] += self.weights[i];
+        }
+    }
+
+    fn interleave(&self, stem_idx: usize, start: usize, frames: usize, dst: &mut Vec<f32>) {
+        dst.clear();
+        let left = &self.acc[stem_idx * 2 * self.capacity..];
+        let right = &self.acc[(stem_idx * 2 + 1) * self.capacity..];
+        for i in start..start + frames {
+            let norm = self.weight_sum[i].max(f32::EPSILON);
+            dst.push(left[i] / norm);
+            dst.push(right[i] / norm);
+        }
+    }
+
+    /// Sum of every stem except `skip`.
+    fn interleave_sum(&self, skip: Option<usize>, start: usize, frames: usize, dst: &mut Vec<f32>) {
+        dst.clear();
+        for i in start..start + frames {
+            let norm = self.weight_sum[i].max(f32::EPSILON);
+            let (mut left, mut right) = (0.0, 0.0);
+            for s in (0..self.stems).filter(|&s| Some(s) != skip) {
+                left += self.acc[s * 2 * self.capacity + i];
+                right += self.acc[(s * 2 + 1) * self.capacity + i];
+            }
+            dst.push(left / norm);
+            dst.push(right / norm);
+        }
+    }
+
+    /// Accumulated frames still pending after `advance(hop)`, for a
+    /// checkpoint: every lane followed by the weight sums.
+    fn tail(&self, hop: usize) -> Vec<f32> {
+        let frames = self.capacity.saturating_sub(hop);
+        let mut tail = Vec::with_capacity((self.stems * 2 + 1) * frames);
+        for lane in self.acc.chunks(self.capacity) {
+            tail.extend_from_slice(&lane[..frames]);
+        }
+        tail.extend_from_slice(&self.weight_sum[..frames]);
+        tail
+    }
+
+    /// Restore pending frames saved by [`OverlapAdd::tail`].
+    fn load_tail(&mut self, tail: &[f32]) -> Result<()> {
+        let lanes = self.stems * 2 + 1;
+        let frames = tail.len() / lanes;
+        if !tail.len().is_multiple_of(lanes) || frames > self.capacity {
+            return Err(anyhow::anyhow!("Checkpoint does not match the overlap-add buffer").into());
+        }
+        let (acc, weight_sum) = tail.split_at(frames * self.stems * 2);
+        for (lane, saved) in self
+            .acc
+            .chunks_mut(self.capacity)
+            .zip(acc.chunks(frames.max(1)))
+        {
+            lane[..frames].copy_from_slice(saved);
+        }
+        self.weight_sum[..frames].copy_from_slice(weight_sum);
+        Ok(())
+    }
+
+    fn advance(&mut self, frames: usize) {
+        let frames = frames.min(self.capacity);
+        let keep = self.capacity - frames;
+        for lane in self.acc.chunks_mut(self.capacity) {
+            lane.copy_within(frames.., 0);
+            lane[keep..].fill(0.0);
+        }
+        self.weight_sum.copy_within(frames.., 0);
+        self.weight_sum[keep..].fill(0.0);
+    }
+}
+
+/// Refine the sources of one model window against the (augmented) input
+/// window they were separated from: Wiener filtering, then mixture
+/// consistency.
+fn refine_window(out: &mut Array3<f32>, left: &[f32], right: &[f32], opts: &SplitOptions) {
+    let t = out.shape()[2].min(left.len());
+    let mut sources = Array3::zeros((out.shape()[0], 2, t));
+    sources.assign(&out.slice(s![.., .., ..t]));
+    let planar = sources
+        .as_slice_mut()
+        .expect("freshly allocated arrays are contiguous");
+    wiener_filter(
+        &left[..t],
+        &right[..t],
+        planar,
+        opts.wiener_iterations,
+        WIENER_NFFT,
+        WIENER_HOP,
+    );
+    mix_consistency(&left[..t], &right[..t], planar, opts.consistency);
+    out.slice_mut(s![.., .., ..t]).assign(&sources);
+}
+
+fn overlap_stride(win: usize, manifest_hop: usize, overlap: Option<f32>) -> Result<usize> {
+    match overlap {
+        None => Ok(manifest_hop),
+        Some(ratio) if (0.0..1.0).contains(&ratio) => {
+            Ok((((1.0 - ratio as f64) * win as f64).round() as usize).clamp(1, win))
+        }
+        Some(ratio) => Err(anyhow::anyhow!("overlap must be in [0, 1), got {}", ratio).into()),
+    }
+}
+
+const READ_BLOCK_FRAMES: usize = 16 * 1024;
+
+/// Where [`InputWindow`] pulls interleaved frames from.
+enum InputSource<'a> {
+    Decoder(AudioReader),
+    Memory { audio: &'a AudioData, pos: usize },
+    Prefetched(PrefetchReader),
+}
+
+impl InputSource<'_> {
+    fn sample_rate(&self) -> u32 {
+        match self {
+            InputSource::Decoder(reader) => reader.sample_rate(),
+            InputSource::Memory { audio, .. } => audio.sample_rate,
+            InputSource::Prefetched(reader) => reader.sample_rate,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self {
+            InputSource::Decoder(reader) => reader.channels(),
+            InputSource::Memory { audio, .. } => audio.channels,
+            InputSource::Prefetched(reader) => reader.channels,
+        }
+    }
+
+    fn metadata(&self) -> &AudioMetadata {
+        match self {
+            InputSource::Decoder(reader) => reader.metadata(),
+            InputSource::Memory { audio, .. } => &audio.metadata,
+            InputSource::Prefetched(reader) => &reader.metadata,
+        }
+    }
+
+    fn damage(&self) -> DecodeDamage {
+        match self {
+            InputSource::Decoder(reader) => reader.damage(),
+            InputSource::Memory { audio, .. } => audio.damage,
+            InputSource::Prefetched(reader) => reader.damage,
+        }
+    }
+
+    fn n_frames(&self) -> Option<u64> {
+        match self {
+            InputSource::Decoder(reader) => reader.n_frames(),
+            InputSource::Memory { audio, .. } => {
+                Some((audio.samples.len() / usize::from(audio.channels.max(1))) as u64)
+            }
+            InputSource::Prefetched(reader) => reader.n_frames,
+        }
+    }
+
+    fn seek(&mut self, frame: usize) -> Result<()> {
+        match self {
+            InputSource::Decoder(reader) => Ok(reader.seek(frame as u64)?),
+            InputSource::Memory { audio, pos } => {
+                let total = audio.samples.len() / usize::from(audio.channels.max(1));
+                *pos = frame.min(total);
+                Ok(())
+            }
+            InputSource::Prefetched(reader) => reader.seek(frame),
+        }
+    }
+
+    fn read_frames(&mut self, max_frames: usize, dst: &mut Vec<f32>) -> Result<usize> {
+        match self {
+            InputSource::Decoder(reader) => Ok(reader.read_frames(max_frames, dst)?),
+            InputSource::Memory { audio, pos } => {
+                let channels = usize::from(audio.channels.max(1));
+                let total = audio.samples.len() / channels;
+                let frames = max_frames.min(total - *pos);
+                dst.extend_from_slice(&audio.samples[*pos * channels..(*pos + frames) * channels]);
+                *pos += frames;
+                Ok(frames)
+            }
+            InputSource::Prefetched(reader) => reader.read_frames(max_frames, dst),
+        }
+    }
+}
+
+/// Blocks a [`Prefetcher`] may decode ahead of the split consuming them.
+const PREFETCH_BLOCKS: usize = 8;
+
+enum Prefetched {
+    Opened {
+        sample_rate: u32,
+        channels: u16,
+        n_frames: Option<u64>,
+        metadata: AudioMetadata,
+        position: usize,
+    },
+    /// A block of frames and the damage found up to its end.
+    Frames(Vec<f32>, DecodeDamage),
+}
+
+/// Decodes a file on a background thread, at most [`PREFETCH_BLOCKS`] blocks
+/// ahead of the reader, so the next input of a batch is decoded while the
+/// current one is in inference.
+///
+/// The thread seeks to the start of the span the split will decode, and exits
+/// at the end of the file or once the receiving side is dropped.
+struct Prefetcher {
+    rx: Receiver<anyhow::Result<Prefetched>>,
+}
+
+impl Prefetcher {
+    fn spawn(path: String, opts: SplitOptions, mf: &'static ModelManifest) -> Self {
+        let (tx, rx) = mpsc::sync_channel(PREFETCH_BLOCKS);
+        thread::spawn(move || {
+            let decode = || -> anyhow::Result<()> {
+                let mut reader = AudioReader::open_track(&path, &opts.track)?;
+                // An invalid range is reported by the split itself.
+                let position = DecodeSpan::new(&opts, mf, reader.sample_rate())
+                    .map_or(0, |span| span.decode_start);
+                if position > 0 {
+                    reader.seek(position as u64)?;
+                }
+                let opened = Prefetched::Opened {
+                    sample_rate: reader.sample_rate(),
+                    channels: reader.channels(),
+                    n_frames: reader.n_frames(),
+                    metadata: reader.metadata().clone(),
+                    position,
+                };
+                if tx.send(Ok(opened)).is_err() {
+                    return Ok(());
+                }
+
+                let channels = usize::from(reader.channels().max(1));
+                loop {
+                    let mut block = Vec::with_capacity(READ_BLOCK_FRAMES * channels);
+                    let frames = reader.read_frames(READ_BLOCK_FRAMES, &mut block)?;
+                    // The empty last block reports damage found at the end.
+                    if tx
+                        .send(Ok(Prefetched::Frames(block, reader.damage())))
+                        .is_err()
+                        || frames == 0
+                    {
+                        return Ok(());
+                    }
+                }
+            };
+            if let Err(e) = decode() {
+                tx.send(Err(e)).ok();
+            }
+        });
+        Self { rx }
+    }
+
+    /// Wait until the file is open and its format is known.
+    fn into_reader(self) -> Result<PrefetchReader> {
+        match self.rx.recv() {
+            Ok(Ok(Prefetched::Opened {
+                sample_rate,
+                channels,
+                n_frames,
+                metadata,
+                position,
+            })) => Ok(PrefetchReader {
+                rx: self.rx,
+                sample_rate,
+                channels,
+                n_frames,
+                metadata,
+                position,
+                pending: Vec::new(),
+                finished: false,
+                damage: DecodeDamage::default(),
+            }),
+            Ok(Err(e)) => Err(e.into()),
+            Ok(Ok(Prefetched::Frames(..))) | Err(_) => {
+                Err(anyhow::anyhow!("Audio decoder thread stopped unexpectedly").into())
+            }
+        }
+    }
+}
+
+struct PrefetchReader {
+    rx: Receiver<anyhow::Result<Prefetched>>,
+    sample_rate: u32,
     channels: u16,
-    start_frame: usize,
-    left_raw: &mut [f32],
-    right_raw: &mut [f32],
-) {
-    let channels = usize::from(channels.max(1));
-
-    for i in 0..left_raw.len() {
-        let frame = start_frame + i;
-        let base = frame * channels;
-        if base >= samples.len() {
-            left_raw[i] = 0.0;
-            right_raw[i] = 0.0;
-            continue;
-        }
-
-        let left = samples[base];
-        let right = if channels == 1 {
-            left
+    n_frames: Option<u64>,
+    metadata: AudioMetadata,
+    /// Source frame index of the first sample in `pending`.
+    position: usize,
+    pending: Vec<f32>,
+    finished: bool,
+    damage: DecodeDamage,
+}
+
+impl PrefetchReader {
+    fn read_frames(&mut self, max_frames: usize, dst: &mut Vec<f32>) -> Result<usize> {
+        let channels = usize::from(self.channels.max(1));
+        let wanted = max_frames * channels;
+
+        while self.pending.len() < wanted && !self.finished {
+            match self.rx.recv() {
+                Ok(Ok(Prefetched::Frames(block, damage))) => {
+                    self.pending.extend_from_slice(&block);
+                    self.damage = damage;
+                }
+                Ok(Ok(Prefetched::Opened { .. })) => {}
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => self.finished = true,
+            }
+        }
+
+        let take = wanted.min(self.pending.len());
+        dst.extend_from_slice(&self.pending[..take]);
+        self.pending.drain(..take);
+        self.position += take / channels;
+        Ok(take / channels)
+    }
+
+    /// Frames only arrive in order, so this can skip ahead but not go back.
+    fn seek(&mut self, frame: usize) -> Result<()> {
+        if frame < self.position {
+            return Err(anyhow::anyhow!(
+                "Cannot seek back to frame {} in a prefetched input at frame {}",
+                frame,
+                self.position
+            )
+            .into());
+        }
+        let mut skipped = Vec::new();
+        while self.position < frame {
+            skipped.clear();
+            let wanted = (frame - self.position).min(READ_BLOCK_FRAMES);
+            if self.read_frames(wanted, &mut skipped)? == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Role of an input channel, as far as downmixing and pairing care.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+    BackCenter,
+}
+
+impl Speaker {
+    /// Speakers of a `channels`-channel input in WAV channel order. Layouts
+    /// past 7.1 continue with alternating surround pairs.
+    fn layout(channels: usize) -> Vec<Speaker> {
+        use Speaker::*;
+        let known: &[Speaker] = match channels {
+            3 => &[FrontLeft, FrontRight, Center],
+            4 => &[FrontLeft, FrontRight, SurroundLeft, SurroundRight],
+            5 => &[FrontLeft, FrontRight, Center, SurroundLeft, SurroundRight],
+            6 => &[
+                FrontLeft,
+                FrontRight,
+                Center,
+                Lfe,
+                SurroundLeft,
+                SurroundRight,
+            ],
+            7 => &[
+                FrontLeft,
+                FrontRight,
+                Center,
+                Lfe,
+                BackCenter,
+                SurroundLeft,
+                SurroundRight,
+            ],
+            8 => &[
+                FrontLeft,
+                FrontRight,
+                Center,
+                Lfe,
+                SurroundLeft,
+                SurroundRight,
+                SurroundLeft,
+                SurroundRight,
+            ],
+            _ => &[FrontLeft, FrontRight],
+        };
+        (0..channels)
+            .map(|ch| match known.get(ch) {
+                Some(&speaker) => speaker,
+                None if ch % 2 == 0 => SurroundLeft,
+                None => SurroundRight,
+            })
+            .collect()
+    }
+
+    /// ITU-R BS.775 gains into the left and right of a stereo downmix.
+    fn downmix_gains(self) -> [f32; 2] {
+        match self {
+            Speaker::FrontLeft => [1.0, 0.0],
+            Speaker::FrontRight => [0.0, 1.0],
+            Speaker::Center => [DOWNMIX_GAIN, DOWNMIX_GAIN],
+            Speaker::Lfe => [0.0, 0.0],
+            Speaker::SurroundLeft => [DOWNMIX_GAIN, 0.0],
+            Speaker::SurroundRight => [0.0, DOWNMIX_GAIN],
+            // Split between both surrounds before they are downmixed.
+            Speaker::BackCenter => [0.5, 0.5],
+        }
+    }
+
+    /// Weight of the channel in BS.1770 integrated loudness. Back channels
+    /// count as surrounds, as in common EBU R128 meters.
+    fn loudness_weight(self) -> f32 {
+        match self {
+            Speaker::FrontLeft | Speaker::FrontRight | Speaker::Center => 1.0,
+            Speaker::Lfe => 0.0,
+            Speaker::SurroundLeft | Speaker::SurroundRight | Speaker::BackCenter => 1.41,
+        }
+    }
+
+    /// Whether `self` followed by `next` is a left/right pair.
+    fn pairs_with(self, next: Speaker) -> bool {
+        matches!(
+            (self, next),
+            (Speaker::FrontLeft, Speaker::FrontRight)
+                | (Speaker::SurroundLeft, Speaker::SurroundRight)
+        )
+    }
+}
+
+/// How the input channels are fed to the stereo model, and where the
+/// separated stereo goes in the stems.
+///
+/// The input is separated as one or more stereo pairs. A single channel
+/// separated on its own is fed to both sides of the model and gets the
+/// average of both sides back.
+#[derive(Clone, Debug)]
+struct ChannelMap {
+    /// Gains of every input channel into the left and right model input,
+    /// for each pair.
+    inputs: Vec<Vec<[f32; 2]>>,
+    /// Stem channels the left and right of each pair's output go to.
+    outputs: Vec<[usize; 2]>,
+    /// Channels of the stems.
+    stem_channels: usize,
+}
+
+impl ChannelMap {
+    fn new(mode: MultichannelMode, channels: usize) -> Result<Self> {
+        let one_hot = |left: usize, right: usize| -> Vec<[f32; 2]> {
+            (0..channels)
+                .map(|ch| [(ch == left) as u8 as f32, (ch == right) as u8 as f32])
+                .collect()
+        };
+        let stereo = |inputs| Self {
+            inputs: vec![inputs],
+            outputs: vec![[0, 1]],
+            stem_channels: 2,
+        };
+
+        match mode {
+            // A chosen pair applies to any input, so one that does not exist
+            // is an error even for mono and stereo.
+            MultichannelMode::Pair { left, right } => {
+                if left >= channels || right >= channels {
+                    return Err(anyhow::anyhow!(
+                        "Channel pair {},{} is out of range for a {}-channel input",
+                        left,
+                        right,
+                        channels
+                    )
+                    .into());
+                }
+                Ok(stereo(one_hot(left, right)))
+            }
+            _ if channels <= 2 => Ok(stereo(one_hot(0, channels - 1))),
+            MultichannelMode::Downmix => Ok(stereo(
+                Speaker::layout(channels)
+                    .into_iter()
+                    .map(Speaker::downmix_gains)
+                    .collect(),
+            )),
+            MultichannelMode::PerPair => {
+                let layout = Speaker::layout(channels);
+                let mut outputs = Vec::new();
+                let mut ch = 0;
+                while ch < channels {
+                    if layout
+                        .get(ch + 1)
+                        .is_some_and(|&next| layout[ch].pairs_with(next))
+                    {
+                        outputs.push([ch, ch + 1]);
+                        ch += 2;
+                    } else {
+                        outputs.push([ch, ch]);
+                        ch += 1;
+                    }
+                }
+                Ok(Self {
+                    inputs: outputs
+                        .iter()
+                        .map(|&[left, right]| one_hot(left, right))
+                        .collect(),
+                    outputs,
+                    stem_channels: channels,
+                })
+            }
+        }
+    }
+
+    fn pairs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Write interleaved stereo `frames` separated from `pair` into their
+    /// channels of the interleaved stem frames `dst`.
+    fn scatter(&self, pair: usize, frames: &[f32], dst: &mut [f32]) {
+        let [left, right] = self.outputs[pair];
+        for (frame, out) in frames
+            .chunks_exact(2)
+            .zip(dst.chunks_exact_mut(self.stem_channels))
+        {
+            if left == right {
+                out[left] = (frame[0] + frame[1]) * 0.5;
+            } else {
+                out[left] = frame[0];
+                out[right] = frame[1];
+            }
+        }
+    }
+}
+
+/// Sliding view over the decoded input at the model sample rate.
+///
+/// Frames are pulled from the decoder only when a window needs them and are
+/// dropped once no later window can reach them, so memory stays bounded by
+/// the window size rather than the track length.
+struct InputWindow<'a> {
+    source: InputSource<'a>,
+    resampler: Option<StreamResampler>,
+    channels: usize,
+    map: ChannelMap,
+    buf: Vec<f32>,
+    base: usize,
+    decoded: Vec<f32>,
+    /// Source frame the decoded span starts at.
+    first_frame: usize,
+    /// Source frames to decode from `first_frame`; `None` reads to the end.
+    limit: Option<usize>,
+    source_frames: usize,
+    eof: bool,
+}
+
+impl<'a> InputWindow<'a> {
+    fn open(
+        path: &str,
+        track: &TrackSelector,
+        multichannel: MultichannelMode,
+        model_rate: u32,
+    ) -> Result<Self> {
+        Self::new(
+            InputSource::Decoder(AudioReader::open_track(path, track)?),
+            multichannel,
+            model_rate,
+        )
+    }
+
+    fn from_audio(
+        audio: &'a AudioData,
+        multichannel: MultichannelMode,
+        model_rate: u32,
+    ) -> Result<Self> {
+        if audio.sample_rate == 0 || audio.channels == 0 {
+            return Err(anyhow::anyhow!(
+                "Invalid audio: {} Hz, {} channels",
+                audio.sample_rate,
+                audio.channels
+            )
+            .into());
+        }
+        Self::new(
+            InputSource::Memory { audio, pos: 0 },
+            multichannel,
+            model_rate,
+        )
+    }
+
+    fn new(
+        source: InputSource<'a>,
+        multichannel: MultichannelMode,
+        model_rate: u32,
+    ) -> Result<Self> {
+        let channels = usize::from(source.channels().max(1));
+        let map = ChannelMap::new(multichannel, channels)?;
+        let resampler = if source.sample_rate() != model_rate {
+            Some(StreamResampler::new(
+                source.sample_rate(),
+                model_rate,
+                channels,
+            )?)
         } else {
-            samples.get(base + 1).copied().unwrap_or(left)
+            None
         };
 
-        left_raw[i] = left;
-        right_raw[i] = right;
+        Ok(Self {
+            source,
+            resampler,
+            channels,
+            map,
+            buf: Vec::new(),
+            base: 0,
+            decoded: Vec::with_capacity(READ_BLOCK_FRAMES * channels),
+            first_frame: 0,
+            limit: None,
+            source_frames: 0,
+            eof: false,
+        })
+    }
+
+    fn source_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn source_metadata(&self) -> &AudioMetadata {
+        self.source.metadata()
+    }
+
+    fn decode_damage(&self) -> DecodeDamage {
+        self.source.damage()
+    }
+
+    /// Stereo pairs the input is separated as.
+    fn pairs(&self) -> usize {
+        self.map.pairs()
+    }
+
+    fn stem_channels(&self) -> usize {
+        self.map.stem_channels
     }
+
+    /// Restrict decoding to source frames `[start, end)`, seeking the source
+    /// to `start`. Must be called before the first read.
+    fn restrict(&mut self, start: usize, end: Option<usize>) -> Result<()> {
+        debug_assert_eq!(self.source_frames, 0, "restrict after reading");
+        if start > 0 {
+            self.source.seek(start)?;
+        }
+        self.first_frame = start;
+        self.limit = end.map(|end| end.saturating_sub(start));
+        Ok(())
+    }
+
+    /// Estimated length at the model rate, for progress reporting.
+    fn estimated_frames(&self, model_rate: u32) -> Option<usize> {
+        self.source.n_frames().map(|frames| {
+            let frames = (frames as usize).saturating_sub(self.first_frame);
+            let frames = self.limit.map_or(frames, |limit| frames.min(limit));
+            (frames as f64 * model_rate as f64 / self.source_rate() as f64).round() as usize
+        })
+    }
+
+    /// Track length at the model rate, known once the decoder hit the end.
+    fn total_frames(&self) -> Option<usize> {
+        self.eof.then(|| self.buffered_end())
+    }
+
+    fn buffered_end(&self) -> usize {
+        self.base + self.buf.len() / self.channels
+    }
+
+    fn ensure(&mut self, end_frame: usize) -> Result<()> {
+        while !self.eof && self.buffered_end() < end_frame {
+            self.decoded.clear();
+            let max_frames = self.limit.map_or(READ_BLOCK_FRAMES, |limit| {
+                READ_BLOCK_FRAMES.min(limit - self.source_frames)
+            });
+            let frames = if max_frames > 0 {
+                self.source.read_frames(max_frames, &mut self.decoded)?
+            } else {
+                0
+            };
+            self.source_frames += frames;
+
+            match self.resampler.as_mut() {
+                Some(resampler) if frames > 0 => {
+                    resampler.process_interleaved(&self.decoded, &mut self.buf)?
+                }
+                Some(resampler) => {
+                    let total = resampler.expected_output_frames();
+                    resampler.flush(total, &mut self.buf)?;
+                }
+                None => self.buf.extend_from_slice(&self.decoded),
+            }
+
+            if frames == 0 {
+                self.eof = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_window(
+        &mut self,
+        pair: usize,
+        start_frame: isize,
+        left_raw: &mut [f32],
+        right_raw: &mut [f32],
+    ) -> Result<()> {
+        let end = start_frame + left_raw.len() as isize;
+        if end > 0 {
+            self.ensure(end as usize)?;
+        }
+
+        for i in 0..left_raw.len() {
+            let (left, right) = self.pair_frame(pair, start_frame + i as isize);
+            left_raw[i] = left;
+            right_raw[i] = right;
+        }
+        Ok(())
+    }
+
+    /// Model input `pair` of a buffered frame; silence outside the decoded
+    /// range.
+    fn pair_frame(&self, pair: usize, frame: isize) -> (f32, f32) {
+        if frame < self.base as isize {
+            debug_assert!(frame < 0, "frame {} already discarded", frame);
+            return (0.0, 0.0);
+        }
+
+        let base = (frame as usize - self.base) * self.channels;
+        let Some(samples) = self.buf.get(base..base + self.channels) else {
+            return (0.0, 0.0);
+        };
+        samples.iter().zip(&self.map.inputs[pair]).fold(
+            (0.0, 0.0),
+            |(left, right), (sample, [to_left, to_right])| {
+                (left + sample * to_left, right + sample * to_right)
+            },
+        )
+    }
+
+    /// Replace interleaved stereo `frames` of model input `pair` starting at
+    /// `start_frame` with the input mixture minus their current value.
+    fn subtract_from_mix(&self, pair: usize, start_frame: usize, frames: &mut [f32]) {
+        for (i, frame) in frames.chunks_exact_mut(2).enumerate() {
+            let (left, right) = self.pair_frame(pair, (start_frame + i) as isize);
+            frame[0] = left - frame[0];
+            frame[1] = right - frame[1];
+        }
+    }
+
+    /// Decode up to `frame` and drop everything before it, without holding
+    /// more than a block in memory.
+    fn skip_to(&mut self, frame: usize) -> Result<()> {
+        while !self.eof && self.buffered_end() < frame {
+            let next = (self.buffered_end() + READ_BLOCK_FRAMES).min(frame);
+            self.ensure(next)?;
+            self.discard_before(next);
+        }
+        self.discard_before(frame);
+        Ok(())
+    }
+
+    /// Drop buffered frames before `frame`; no later window may read them.
+    fn discard_before(&mut self, frame: usize) {
+        let drop_frames = frame
+            .saturating_sub(self.base)
+            .min(self.buf.len() / self.channels);
+        if drop_frames > 0 {
+            self.buf.drain(..drop_frames * self.channels);
+            self.base += drop_frames;
+        }
+    }
+}
+
+/// Names for the `count` sources produced by the model, in output order.
+///
+/// Manifest names are used where present. Sources beyond the manifest list are
+/// named `source<N>`, and duplicate names get their index appended so every
+/// stem ends up in its own file.
+fn stem_names(manifest_stems: &[String], count: usize) -> Vec<String> {
+    let mut seen = HashSet::new();
+    (0..count)
+        .map(|i| {
+            let name = match manifest_stems.get(i) {
+                Some(name) => name.to_lowercase(),
+                None if manifest_stems.is_empty() && count == DEFAULT_STEMS.len() => {
+                    DEFAULT_STEMS[i].to_string()
+                }
+                None => format!("source{}", i),
+            };
+            if seen.insert(name.clone()) {
+                name
+            } else {
+                format!("{}_{}", name, i)
+            }
+        })
+        .collect()
 }
 
-fn build_output_paths(input_path: &str, output_dir: &str) -> (String, String, String, String) {
+/// Resolve which stems to write from the model's source names, applying the
+/// two-stem mode and the wanted-stem filter from `opts`.
+fn plan_stems(model_names: &[String], opts: &SplitOptions) -> Result<Vec<(String, StemSource)>> {
+    let mut plan: Vec<(String, StemSource)> = match &opts.two_stems {
+        Some(stem) => {
+            let wanted = stem.to_lowercase();
+            let idx = model_names
+                .iter()
+                .position(|name| *name == wanted)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Unknown stem '{}' for two-stem mode (model provides: {})",
+                        stem,
+                        model_names.join(", ")
+                    )
+                })?;
+            let complement = match opts.complement {
+                ComplementMethod::Sum => StemSource::Others(idx),
+                ComplementMethod::Minus => StemSource::MixMinus(idx),
+            };
+            vec![
+                (wanted.clone(), StemSource::Model(idx)),
+                (format!("no_{}", wanted), complement),
+            ]
+        }
+        None => model_names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (name.clone(), StemSource::Model(idx)))
+            .collect(),
+    };
+
+    if !opts.stems.is_empty() {
+        let wanted: Vec<String> = opts.stems.iter().map(|s| s.to_lowercase()).collect();
+        if let Some(unknown) = wanted
+            .iter()
+            .find(|w| !plan.iter().any(|(name, _)| name == *w))
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown stem '{}' (available: {})",
+                unknown,
+                plan.iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        plan.retain(|(name, _)| wanted.contains(name));
+    }
+
+    Ok(plan)
+}
+
+fn build_output_path(
+    input_path: &str,
+    output_dir: &str,
+    stem_name: &str,
+    format: OutputFormat,
+) -> String {
     let file_stem = Path::new(input_path)
         .file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("output");
     let base = PathBuf::from(output_dir).join(file_stem);
 
-    (
-        format!("{}_vocals.wav", base.to_string_lossy()),
-        format!("{}_drums.wav", base.to_string_lossy()),
-        format!("{}_bass.wav", base.to_string_lossy()),
-        format!("{}_other.wav", base.to_string_lossy()),
+    format!(
+        "{}_{}.{}",
+        base.to_string_lossy(),
+        stem_name,
+        format.extension()
     )
 }
 
-fn build_stem_outputs(
-    names: &[String],
-    stems_count: usize,
-    sample_rate: u32,
-    vocals_out: String,
-    drums_out: String,
-    bass_out: String,
-    other_out: String,
-) -> Result<Vec<StemOutput>> {
-    let mut name_idx: HashMap<String, usize> = HashMap::new();
-    for (i, name) in names.iter().enumerate() {
-        name_idx.insert(name.to_lowercase(), i);
-    }
-
-    let get_idx = |key: &str, fallback: usize| -> usize {
-        name_idx
-            .get(key)
-            .copied()
-            .unwrap_or(fallback.min(stems_count.saturating_sub(1)))
+/// Directory holding the checkpoint of a split of `input_path`, next to its
+/// stems.
+fn build_checkpoint_dir(input_path: &str, output_dir: &str) -> PathBuf {
+    let file_stem = Path::new(input_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("output");
+    PathBuf::from(output_dir).join(format!("{}.checkpoint", file_stem))
+}
+
+/// `metadata` of the source with the stem name appended to its title.
+fn stem_metadata(metadata: &AudioMetadata, stem_name: &str) -> AudioMetadata {
+    let mut stem = metadata.clone();
+    if let Some(title) = metadata.get("TITLE") {
+        stem.set("TITLE", format!("{} ({})", title, stem_name));
+    }
+    stem
+}
+
+fn build_stem_outputs<S: StemSink>(
+    plan: Vec<(String, StemSource)>,
+    model_rate: u32,
+    output_rate: u32,
+    trim: Trim,
+    gain: GainStaging,
+    metadata: &AudioMetadata,
+    make_sink: &mut impl FnMut(&str, u32, &AudioMetadata) -> Result<S>,
+) -> Result<Vec<StemOutput<S>>> {
+    plan.into_iter()
+        .map(|(name, source)| {
+            let sink = make_sink(&name, output_rate, &stem_metadata(metadata, &name))?;
+            let channels = sink.channels();
+            let resampler = if model_rate == output_rate {
+                None
+            } else {
+                Some(StreamResampler::new(model_rate, output_rate, channels)?)
+            };
+            let stage = match gain {
+                GainStaging::Off => GainStage::Direct,
+                GainStaging::Limit { ceiling_db } => GainStage::Limit(Limiter::with_channels(
+                    output_rate,
+                    channels as u16,
+                    ceiling_db,
+                )),
+                GainStaging::Common { .. }
+                | GainStaging::NormalizePeak { .. }
+                | GainStaging::NormalizeLoudness { .. } => GainStage::Spool(sink.spool()?),
+            };
+            Ok(StemOutput {
+                source,
+                sink,
+                stem_name: name,
+                channels,
+                resampler,
+                trim,
+                produced: 0,
+                meter: Meter::with_channel_weights(
+                    output_rate,
+                    &Speaker::layout(channels)
+                        .into_iter()
+                        .map(Speaker::loudness_weight)
+                        .collect::<Vec<_>>(),
+                ),
+                stage,
+                limited: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// The `start`/`end` range of `opts` as source frames `[start, end)`.
+fn requested_range(opts: &SplitOptions, source_rate: u32) -> Result<(usize, Option<usize>)> {
+    let to_frame = |name: &str, secs: f64| {
+        if secs.is_finite() && secs >= 0.0 {
+            Ok((secs * source_rate as f64).round() as usize)
+        } else {
+            Err(StemError::from(anyhow::anyhow!(
+                "{} time must be a non-negative number of seconds, got {}",
+                name,
+                secs
+            )))
+        }
     };
 
-    Ok(vec![
-        StemOutput {
-            stem_idx: get_idx("vocals", 0),
-            stem_name: "vocals".to_string(),
-            writer: create_wav_writer(&vocals_out, sample_rate, 2)?,
-        },
-        StemOutput {
-            stem_idx: get_idx("drums", 1),
-            stem_name: "drums".to_string(),
-            writer: create_wav_writer(&drums_out, sample_rate, 2)?,
-        },
-        StemOutput {
-            stem_idx: get_idx("bass", 2),
-            stem_name: "bass".to_string(),
-            writer: create_wav_writer(&bass_out, sample_rate, 2)?,
-        },
-        StemOutput {
-            stem_idx: get_idx("other", 3),
-            stem_name: "other".to_string(),
-            writer: create_wav_writer(&other_out, sample_rate, 2)?,
-        },
-    ])
+    let start = opts.start.map_or(Ok(0), |secs| to_frame("Start", secs))?;
+    let end = opts.end.map(|secs| to_frame("End", secs)).transpose()?;
+    if let Some(end) = end {
+        if end <= start {
+            return Err(anyhow::anyhow!(
+                "End time ({}s) must be after start time ({}s)",
+                opts.end.unwrap_or_default(),
+                opts.start.unwrap_or_default()
+            )
+            .into());
+        }
+    }
+    Ok((start, end))
 }
 
-pub fn split_file(input_path: &str, opts: SplitOptions) -> Result<SplitResult> {
+/// Source frames covering the range requested in `opts`.
+///
+/// Half a model window of real audio is decoded on each side of `[start, end)`
+/// so the windows covering its edges get the same context as mid-track.
+#[derive(Clone, Copy, Debug)]
+struct DecodeSpan {
+    start: usize,
+    end: Option<usize>,
+    decode_start: usize,
+    decode_end: Option<usize>,
+}
+
+impl DecodeSpan {
+    fn new(opts: &SplitOptions, mf: &ModelManifest, source_rate: u32) -> Result<Self> {
+        let (start, end) = requested_range(opts, source_rate)?;
+        let context =
+            (mf.window as f64 / 2.0 * source_rate as f64 / mf.sample_rate as f64).ceil() as usize;
+        Ok(Self {
+            start,
+            end,
+            decode_start: start.saturating_sub(context),
+            decode_end: end.map(|end| end + context),
+        })
+    }
+}
+
+fn check_cancelled(opts: &SplitOptions) -> Result<()> {
+    if opts.cancel.is_cancelled() {
+        Err(StemError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Resolve and load the model, returning its manifest.
+fn load_model(opts: &SplitOptions) -> Result<&'static ModelManifest> {
     emit_split_progress(SplitProgress::Stage("resolve_model"));
-    let handle = ensure_model(&opts.model_name, opts.manifest_url_override.as_deref())?;
+    let handle = ensure_model_cancellable(
+        &opts.model_name,
+        opts.manifest_url_override.as_deref(),
+        &opts.cancel,
+    )?;
+    check_cancelled(opts)?;
 
     emit_split_progress(SplitProgress::Stage("engine_preload"));
     engine::preload(&handle)?;
+    check_cancelled(opts)?;
 
     let mf = engine::manifest();
 
@@ -128,21 +1512,262 @@ pub fn split_file(input_path: &str, opts: SplitOptions) -> Result<SplitResult> {
         return Err(anyhow::anyhow!("Currently expecting 44.1k model").into());
     }
 
+    Ok(mf)
+}
+
+pub fn split_file(input_path: &str, opts: SplitOptions) -> Result<SplitResult> {
+    let mf = load_model(&opts)?;
+
     emit_split_progress(SplitProgress::Stage("read_audio"));
-    let audio = read_audio(input_path)?;
-    let n = audio_frame_count(&audio.samples, audio.channels);
+    let input = InputWindow::open(input_path, &opts.track, opts.multichannel, mf.sample_rate)?;
+    split_to_files(input_path, input, mf, &opts)
+}
+
+/// Split every file in `inputs` with the same options, loading the model once.
+///
+/// While one file is in inference the next is already being decoded on a
+/// background thread. A failing input does not stop the batch: each input
+/// gets its own entry in the result, in order. Only a model that cannot be
+/// loaded fails the whole call. Cancelling marks the current and every
+/// remaining input as [`StemError::Cancelled`].
+pub fn split_files<P: AsRef<str>>(inputs: &[P], opts: SplitOptions) -> Result<BatchResult> {
+    let mf = load_model(&opts)?;
+    let total = inputs.len();
+    let prefetch = |idx: usize| {
+        inputs
+            .get(idx)
+            .map(|path| Prefetcher::spawn(path.as_ref().to_string(), opts.clone(), mf))
+    };
 
-    if n == 0 {
-        return Err(anyhow::anyhow!("Empty audio").into());
+    let mut items = Vec::with_capacity(total);
+    let mut next = prefetch(0);
+    for (idx, path) in inputs.iter().enumerate() {
+        let input = path.as_ref();
+        let current = next.take().expect("prefetch started for every input");
+        next = prefetch(idx + 1);
+
+        emit_split_progress(SplitProgress::FileStarted {
+            input: input.to_string(),
+            index: idx + 1,
+            total,
+        });
+        let result = check_cancelled(&opts).and_then(|()| {
+            let reader = current.into_reader()?;
+            let window = InputWindow::new(
+                InputSource::Prefetched(reader),
+                opts.multichannel,
+                mf.sample_rate,
+            )?;
+            split_to_files(input, window, mf, &opts)
+        });
+        emit_split_progress(SplitProgress::FileFinished {
+            input: input.to_string(),
+            index: idx + 1,
+            total,
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        items.push(BatchItem {
+            input: input.to_string(),
+            result,
+        });
+    }
+
+    Ok(BatchResult { items })
+}
+
+/// Run the split of `input_path` into stem files, removing them again if it
+/// fails part-way. With `opts.checkpoint` the checkpoint survives the failure
+/// so the next run can resume.
+fn split_to_files(
+    input_path: &str,
+    input: InputWindow<'_>,
+    mf: &ModelManifest,
+    opts: &SplitOptions,
+) -> Result<SplitResult> {
+    let channels = input.stem_channels();
+    let mut checkpoint = if opts.checkpoint {
+        Some(Checkpoint::open(
+            build_checkpoint_dir(input_path, &opts.output_dir),
+            Identity::new(input_path, mf, opts)?,
+            channels,
+        ))
+    } else {
+        None
+    };
+
+    // Paths of every stem file opened so far, so a failed or cancelled split
+    // does not leave truncated stems behind.
+    let mut created: Vec<String> = Vec::new();
+    let stems = run_split(
+        input,
+        mf,
+        opts,
+        checkpoint.as_mut(),
+        |name, output_rate, metadata| {
+            let path = build_output_path(input_path, &opts.output_dir, name, opts.output_format);
+            let writer = AudioWriter::create_with_metadata(
+                &path,
+                output_rate,
+                channels as u16,
+                opts.output_format,
+                metadata,
+            )?;
+            created.push(path.clone());
+            Ok(FileSink {
+                writer,
+                path,
+                channels: channels as u16,
+            })
+        },
+    );
+
+    match stems {
+        Ok(split) => {
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.remove();
+            }
+            Ok(SplitResult {
+                stems: split
+                    .stems
+                    .into_iter()
+                    .map(|(name, stem)| {
+                        (
+                            name,
+                            StemFile {
+                                path: stem.output,
+                                stats: stem.stats,
+                                gain_db: stem.gain_db,
+                            },
+                        )
+                    })
+                    .collect(),
+                mix_residual: split.mix_residual,
+                windows: split.windows.total,
+                silent_windows: split.windows.silent,
+                decode_damage: split.decode_damage,
+            })
+        }
+        Err(e) => {
+            // Keep the last finished chunk for the next run. If this fails, the
+            // previous sync still describes a consistent state.
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                checkpoint.sync().ok();
+            }
+            for path in &created {
+                std::fs::remove_file(path).ok();
+            }
+            Err(e)
+        }
     }
+}
 
+/// Split decoded audio into in-memory stems without touching the
+/// filesystem. `output_dir`, `output_format` and `checkpoint` in `opts` are
+/// ignored.
+pub fn split_audio(audio: &AudioData, opts: SplitOptions) -> Result<StemBuffers> {
+    let mf = load_model(&opts)?;
+    let input = InputWindow::from_audio(audio, opts.multichannel, mf.sample_rate)?;
+    let channels = input.stem_channels();
+
+    let split = run_split(input, mf, &opts, None, |_, sample_rate, metadata| {
+        Ok(MemorySink {
+            samples: Vec::with_capacity(
+                audio.samples.len() / usize::from(audio.channels) * channels,
+            ),
+            sample_rate,
+            channels: channels as u16,
+            metadata: metadata.clone(),
+        })
+    })?;
+
+    Ok(StemBuffers {
+        stems: split
+            .stems
+            .into_iter()
+            .map(|(name, stem)| (name, stem.output))
+            .collect(),
+    })
+}
+
+/// Windowed inference over `input`, streaming every planned stem into the sink
+/// created for it by `make_sink(stem_name, output_rate, stem_metadata)`.
+///
+/// With a `checkpoint`, emitted samples are journaled and progress is
+/// committed after every chunk (and synced to disk every few seconds); a matching checkpoint from an earlier run is
+/// replayed into the sinks and inference continues after its last chunk.
+fn run_split<S: StemSink>(
+    mut input: InputWindow<'_>,
+    mf: &ModelManifest,
+    opts: &SplitOptions,
+    mut checkpoint: Option<&mut Checkpoint>,
+    mut make_sink: impl FnMut(&str, u32, &AudioMetadata) -> Result<S>,
+) -> Result<SplitStems<S::Output>> {
+    let source_rate = input.source_rate();
     let win = mf.window;
-    let hop = mf.hop;
 
-    if !(win > 0 && hop > 0 && hop <= win) {
+    if !(win > 0 && mf.hop > 0 && mf.hop <= win) {
         return Err(anyhow::anyhow!("Bad win/hop in manifest").into());
     }
 
+    let span = DecodeSpan::new(opts, mf, source_rate)?;
+    let (start, end) = (span.start, span.end);
+    input.restrict(span.decode_start, span.decode_end)?;
+    let lead = start - span.decode_start;
+    let past_end = || {
+        StemError::from(anyhow::anyhow!(
+            "Start time {}s is past the end of the input",
+            opts.start.unwrap_or_default()
+        ))
+    };
+
+    input.ensure(1)?;
+    if input.total_frames() == Some(0) {
+        return Err(if start > 0 {
+            past_end()
+        } else {
+            anyhow::anyhow!("Empty audio").into()
+        });
+    }
+
+    let output_rate = if opts.resample_to_source_rate {
+        source_rate
+    } else {
+        mf.sample_rate
+    };
+    let to_output_frames = |frames: usize| {
+        if output_rate == source_rate {
+            frames
+        } else {
+            (frames as f64 * output_rate as f64 / source_rate as f64).round() as usize
+        }
+    };
+    // Stems begin at `start` in the source, so their timecode does too.
+    let metadata = AudioMetadata {
+        time_reference: input
+            .source_metadata()
+            .time_reference
+            .map(|samples| tags::rescale(samples + start as u64, source_rate, output_rate)),
+        ..input.source_metadata().clone()
+    };
+    let trim = Trim {
+        skip: to_output_frames(lead),
+        keep: end.map(|end| to_output_frames(end - start)),
+    };
+    // The requested range in model-rate input frames, for the mix residual.
+    let to_model_frames = |frames: usize| {
+        (frames as f64 * mf.sample_rate as f64 / source_rate as f64).round() as usize
+    };
+    let mix_range = (
+        to_model_frames(lead),
+        end.map_or(usize::MAX, |end| to_model_frames(end - span.decode_start)),
+    );
+    let mut mix_energy = MixEnergy::default();
+
+    let hop = overlap_stride(win, mf.hop, opts.overlap)?;
+    gain::validate(opts.gain)?;
+    // Peak below which a window skips the model.
+    let skip_below = opts.skip_silence.map(gain::db_to_gain);
+
     if std::env::var("DEBUG_STEMS").is_ok() {
         eprintln!(
             "Window settings: win={}, hop={}, overlap={}",
@@ -152,100 +1777,374 @@ pub fn split_file(input_path: &str, opts: SplitOptions) -> Result<SplitResult> {
         );
     }
 
-    let names = if mf.stems.is_empty() {
-        vec![
-            "vocals".into(),
-            "drums".into(),
-            "bass".into(),
-            "other".into(),
-        ]
+    let pairs = input.pairs();
+    let channels = input.stem_channels();
+    let mut interleaved: Vec<f32> = Vec::with_capacity(channels * hop);
+    let mut pair_frames: Vec<f32> = Vec::with_capacity(2 * hop);
+    let mut resampled: Vec<f32> = Vec::new();
+    let mut stem_outputs: Vec<StemOutput<S>> = Vec::new();
+    // One overlap-add per model input pair.
+    let mut ola: Option<Vec<OverlapAdd>> = None;
+
+    // Test-time augmentation follows reference Demucs: the track is padded by
+    // `max_shift` frames and every step runs one window per random offset and
+    // flip variant. Frames before `max_shift` belong to the padding only.
+    let max_shift = if opts.shifts > 0 {
+        (mf.sample_rate / 2) as usize
     } else {
-        mf.stems.clone()
+        0
     };
+    let mut rng = match opts.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut resume = checkpoint.as_deref_mut().and_then(Checkpoint::take_resume);
+    let offsets: Vec<usize> = match &resume {
+        // Unseeded shifts must stay the ones the interrupted run drew.
+        Some(resume) => resume.offsets.clone(),
+        None if opts.shifts > 0 => (0..opts.shifts)
+            .map(|_| rng.gen_range(0..=max_shift))
+            .collect(),
+        None => vec![0],
+    };
+    let augments = Augment::variants(opts.flip_polarity, opts.flip_channels);
 
-    let (vocals_out, drums_out, bass_out, other_out) =
-        build_output_paths(input_path, &opts.output_dir);
+    let chunks_for = |frames: usize| {
+        let padded_n = frames + max_shift;
+        if padded_n <= hop {
+            1
+        } else {
+            (padded_n - 1) / hop + 1
+        }
+    };
+    let mut total_chunks = input
+        .estimated_frames(mf.sample_rate)
+        .map(chunks_for)
+        .unwrap_or(1);
+    let mut first_chunk = true;
 
-    let mut left_raw = vec![0f32; win];
-    let mut right_raw = vec![0f32; win];
-    let mut stem_outputs: Vec<StemOutput> = Vec::new();
+    // Windows are submitted up to `pipeline.depth()` ahead of the one being
+    // collected, so decoding and STFT of later windows overlap inference.
+    if opts.batch_size == Some(0) {
+        return Err(anyhow::anyhow!("batch_size must be at least 1").into());
+    }
+    let pipeline = engine::WindowPipeline::new(opts.batch_size)?;
+    let windows_per_step = offsets.len() * augments.len() * pairs;
+    let mut in_flight: VecDeque<PendingWindow> = VecDeque::new();
+    // Number of sources the model produces, taken from the manifest until
+    // the first window has been run.
+    let mut sources = (!mf.stems.is_empty()).then_some(mf.stems.len());
+    // Windows handed to the model, and the first of them that may still be
+    // waiting for its batch to fill.
+    let mut model_submitted = 0;
+    let mut batch_start = 0;
+    let mut windows = WindowCounts::default();
+    let mut submit_done = false;
 
-    let mut pos = 0usize;
-    let mut chunk_done = 0usize;
-    let total_chunks = if n <= hop { 1 } else { (n - 1) / hop + 1 };
-    let mut first_chunk = true;
+    let chunks_done = resume.as_ref().map_or(0, |resume| resume.chunks_done);
+    if let Some(resume) = &resume {
+        mix_energy = resume.mix_energy;
+        windows = resume.windows;
+    }
+    let mut pos = chunks_done * hop;
+    let mut chunk_done = chunks_done;
+    let mut submitted = chunks_done * windows_per_step;
+    if chunks_done > 0 {
+        emit_split_progress(SplitProgress::Stage("resume_checkpoint"));
+        input.skip_to(pos.saturating_sub(max_shift))?;
+    }
 
     emit_split_progress(SplitProgress::Stage("infer"));
-    while pos < n {
-        fill_stereo_window(
-            &audio.samples,
-            audio.channels,
-            pos,
-            &mut left_raw,
-            &mut right_raw,
-        );
+    loop {
+        if submit_done && in_flight.is_empty() {
+            break;
+        }
 
-        let out = engine::run_window_demucs(&left_raw, &right_raw)?;
-        let (stems_count, _, t_out) = (out.shape()[0], out.shape()[1], out.shape()[2]);
+        for _ in 0..windows_per_step {
+            while !submit_done && in_flight.len() < pipeline.depth() {
+                let (step, variant) = (submitted / windows_per_step, submitted % windows_per_step);
+                let step_start = step * hop;
+                if variant == 0 && step > 0 && step_start >= max_shift {
+                    // A step exists while its start lies inside the padded track.
+                    input.ensure(step_start - max_shift + 1)?;
+                    if input
+                        .total_frames()
+                        .is_some_and(|n| step_start >= n + max_shift)
+                    {
+                        submit_done = true;
+                        pipeline.flush()?;
+                        batch_start = model_submitted;
+                        break;
+                    }
+                }
 
-        if first_chunk {
-            stem_outputs = build_stem_outputs(
-                &names,
-                stems_count,
-                mf.sample_rate,
-                vocals_out.clone(),
-                drums_out.clone(),
-                bass_out.clone(),
-                other_out.clone(),
-            )?;
-            first_chunk = false;
+                check_cancelled(opts)?;
+                let (pair, variant) = (variant % pairs, variant / pairs);
+                let offset = offsets[variant / augments.len()];
+                let augment = augments[variant % augments.len()];
+                let mut left_raw = vec![0f32; win];
+                let mut right_raw = vec![0f32; win];
+                input.fill_window(
+                    pair,
+                    (step_start + offset) as isize - max_shift as isize,
+                    &mut left_raw,
+                    &mut right_raw,
+                )?;
+                augment.apply(&mut left_raw, &mut right_raw);
+                let refine = opts.wiener_iterations > 0 || opts.consistency != MixConsistency::Off;
+                let mix = refine.then(|| (left_raw.clone(), right_raw.clone()));
+                // Silent windows get silent sources without running the model,
+                // once it is known how many sources that is.
+                let model_window = if sources.is_some()
+                    && skip_below
+                        .is_some_and(|peak| engine::input_peak(&left_raw, &right_raw) < peak)
+                {
+                    None
+                } else {
+                    pipeline.submit(left_raw, right_raw)?;
+                    model_submitted += 1;
+                    if model_submitted - batch_start == pipeline.batch_size() {
+                        batch_start = model_submitted;
+                    }
+                    Some(model_submitted - 1)
+                };
+                in_flight.push_back(PendingWindow {
+                    pair,
+                    offset,
+                    augment,
+                    mix,
+                    model_window,
+                });
+                submitted += 1;
+            }
+
+            check_cancelled(opts)?;
+            let PendingWindow {
+                pair,
+                offset,
+                augment,
+                mix,
+                model_window,
+            } = in_flight
+                .pop_front()
+                .expect("every window of a step is submitted before the next step");
+            windows.total += 1;
+            let mut out = match model_window {
+                Some(idx) => {
+                    // Silent windows do not fill batches, so this window's
+                    // batch may still be waiting for more.
+                    if idx >= batch_start {
+                        pipeline.flush()?;
+                        batch_start = model_submitted;
+                    }
+                    let out = pipeline.next_output()?;
+                    if sources.is_some_and(|n| n != out.shape()[0]) && !first_chunk {
+                        return Err(anyhow::anyhow!(
+                            "Model produced {} sources but silent windows were given {}",
+                            out.shape()[0],
+                            sources.unwrap_or_default()
+                        )
+                        .into());
+                    }
+                    sources = Some(out.shape()[0]);
+                    out
+                }
+                None => {
+                    windows.silent += 1;
+                    Array3::zeros((sources.unwrap_or_default(), 2, win))
+                }
+            };
+            if let Some((left, right)) = mix {
+                refine_window(&mut out, &left, &right, opts);
+            }
+            let stems_count = out.shape()[0];
+
+            if first_chunk {
+                if std::env::var("DEBUG_STEMS").is_ok() && mf.stems.len() != stems_count {
+                    eprintln!(
+                        "Manifest names {} stems but the model produced {}",
+                        mf.stems.len(),
+                        stems_count
+                    );
+                }
+                stem_outputs = build_stem_outputs(
+                    plan_stems(&stem_names(&mf.stems, stems_count), opts)?,
+                    mf.sample_rate,
+                    output_rate,
+                    trim,
+                    opts.gain,
+                    &metadata,
+                    &mut make_sink,
+                )?;
+                let weights = transition_weights(opts.overlap_window, win, win - hop);
+                let mut overlap_add: Vec<OverlapAdd> = (0..pairs)
+                    .map(|_| OverlapAdd::new(weights.clone(), stems_count, max_shift))
+                    .collect();
+                if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                    let names: Vec<String> = stem_outputs
+                        .iter()
+                        .map(|stem_output| stem_output.stem_name.clone())
+                        .collect();
+                    if let Some(resume) = resume.take() {
+                        if resume.stems != names {
+                            return Err(anyhow::anyhow!(
+                                "Checkpoint stems ({}) do not match this split ({})",
+                                resume.stems.join(", "),
+                                names.join(", ")
+                            )
+                            .into());
+                        }
+                        // Every pair's tail has the same length.
+                        let tail = resume.overlap_tail.len() / pairs;
+                        for (pair_ola, pair_tail) in overlap_add
+                            .iter_mut()
+                            .zip(resume.overlap_tail.chunks(tail.max(1)))
+                        {
+                            pair_ola.load_tail(pair_tail)?;
+                        }
+                        for (idx, stem_output) in stem_outputs.iter_mut().enumerate() {
+                            checkpoint.replay(idx, |samples| {
+                                stem_output.write_interleaved(samples, &mut resampled)
+                            })?;
+                        }
+                    }
+                    checkpoint.begin(names, offsets.clone())?;
+                }
+                ola = Some(overlap_add);
+                first_chunk = false;
+            }
+
+            ola.as_mut()
+                .expect("overlap-add initialized on first chunk")[pair]
+                .add_window(&out, offset, augment);
         }
 
-        let copy_len = hop.min(t_out).min(n - pos);
-        for stem_output in &mut stem_outputs {
-            for i in 0..copy_len {
-                stem_output
-                    .writer
-                    .write_sample(sample_to_i16(out[(stem_output.stem_idx, 0, i)]))
-                    .map_err(anyhow::Error::from)?;
-                stem_output
-                    .writer
-                    .write_sample(sample_to_i16(out[(stem_output.stem_idx, 1, i)]))
-                    .map_err(anyhow::Error::from)?;
+        // Every window of this step has been filled, so if the decoder has not
+        // reached the end yet the track extends past `pos + hop`.
+        let padded_n = input.total_frames().map(|n| n + max_shift);
+        let ola = ola
+            .as_mut()
+            .expect("overlap-add initialized on first chunk");
+        let emit_start = pos.max(max_shift);
+        let emit_end = padded_n.map_or(pos + hop, |end| (pos + hop).min(end));
+        if emit_end > emit_start {
+            let (start, frames) = (emit_start - pos, emit_end - emit_start);
+            for (idx, stem_output) in stem_outputs.iter_mut().enumerate() {
+                interleaved.clear();
+                interleaved.resize(frames * channels, 0.0);
+                for (pair, ola) in ola.iter().enumerate() {
+                    match stem_output.source {
+                        StemSource::Model(idx) => {
+                            ola.interleave(idx, start, frames, &mut pair_frames)
+                        }
+                        StemSource::Others(idx) => {
+                            ola.interleave_sum(Some(idx), start, frames, &mut pair_frames)
+                        }
+                        StemSource::MixMinus(idx) => {
+                            ola.interleave(idx, start, frames, &mut pair_frames);
+                            input.subtract_from_mix(pair, emit_start - max_shift, &mut pair_frames);
+                        }
+                    }
+                    input.map.scatter(pair, &pair_frames, &mut interleaved);
+                }
+                stem_output.write_interleaved(&interleaved, &mut resampled)?;
+                if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                    checkpoint.append(idx, &interleaved)?;
+                }
+            }
+
+            let first = emit_start - max_shift;
+            let (lo, hi) = (first.max(mix_range.0), (first + frames).min(mix_range.1));
+            if hi > lo {
+                for (pair, ola) in ola.iter().enumerate() {
+                    ola.interleave_sum(None, start + (lo - first), hi - lo, &mut pair_frames);
+                    input.subtract_from_mix(pair, lo, &mut pair_frames);
+                    mix_energy.add(&input, pair, lo, &pair_frames);
+                }
             }
         }
+        ola.iter_mut().for_each(|ola| ola.advance(hop));
+        input.discard_before((pos + hop).saturating_sub(max_shift));
+        pos += hop;
+        let finished = padded_n.is_some_and(|end| pos >= end);
 
+        if let Some(n) = input.total_frames() {
+            total_chunks = chunks_for(n);
+        }
         chunk_done += 1;
+        if !finished {
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                let tail: Vec<f32> = ola.iter().flat_map(|ola| ola.tail(hop)).collect();
+                checkpoint.commit(chunk_done, &tail, mix_energy, windows)?;
+            }
+        }
+        total_chunks = total_chunks.max(chunk_done);
         emit_split_progress(SplitProgress::Chunks {
             done: chunk_done,
             total: total_chunks,
             percent: chunk_done as f32 / total_chunks as f32 * 100.0,
+            silent_windows: windows.silent,
         });
 
-        if pos + hop >= n {
+        if finished {
             break;
         }
-        pos += hop;
     }
 
+    let n = input
+        .total_frames()
+        .expect("decoder reached the end of the input");
+    if input.source_frames <= lead {
+        return Err(past_end());
+    }
+    let output_frames = if output_rate == mf.sample_rate {
+        n
+    } else {
+        input.source_frames
+    };
+
+    check_cancelled(opts)?;
     emit_split_progress(SplitProgress::Stage("write_stems"));
-    for (idx, stem_output) in stem_outputs.into_iter().enumerate() {
+    let mut stems = IndexMap::with_capacity(stem_outputs.len());
+    let mix_residual = mix_energy.residual();
+    let total_stems = stem_outputs.len();
+    let flushed = stem_outputs
+        .into_iter()
+        .map(|stem_output| stem_output.flush(output_frames, &mut resampled))
+        .collect::<Result<Vec<_>>>()?;
+    let levels: Vec<StemStats> = flushed.iter().map(|stem| stem.levels).collect();
+    let gains = gain::stem_gains(opts.gain, &levels);
+    for (idx, (stem, gain)) in flushed.into_iter().zip(gains).enumerate() {
         emit_split_progress(SplitProgress::Writing {
-            stem: stem_output.stem_name,
+            stem: stem.stem_name.clone(),
             done: idx + 1,
-            total: 4,
-            percent: (idx + 1) as f32 / 4.0 * 100.0,
+            total: total_stems,
+            percent: (idx + 1) as f32 / total_stems as f32 * 100.0,
         });
-        stem_output.writer.finalize().map_err(anyhow::Error::from)?;
+        stems.insert(stem.stem_name.clone(), stem.finish(gain)?);
     }
 
     emit_split_progress(SplitProgress::Stage("finalize"));
     emit_split_progress(SplitProgress::Finished);
 
-    Ok(SplitResult {
-        vocals_path: vocals_out,
-        drums_path: drums_out,
-        bass_path: bass_out,
-        other_path: other_out,
+    if std::env::var("DEBUG_STEMS").is_ok() && windows.silent > 0 {
+        eprintln!(
+            "Skipped inference on {} of {} windows (silent)",
+            windows.silent, windows.total
+        );
+    }
+    let decode_damage = input.decode_damage();
+    if std::env::var("DEBUG_STEMS").is_ok() && !decode_damage.is_clean() {
+        eprintln!(
+            "Skipped {} corrupt packets ({} frames) while decoding the input",
+            decode_damage.skipped_packets, decode_damage.dropped_frames
+        );
+    }
+
+    Ok(SplitStems {
+        stems,
+        mix_residual,
+        windows,
+        decode_damage,
     })
 }
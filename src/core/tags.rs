@@ -0,0 +1,427 @@
+//! Tags, cover art and BWF timecode: read from any input symphonia decodes,
+//! written into WAV (LIST/INFO, `id3 ` and `bext` chunks) and FLAC (Vorbis
+//! comment and picture blocks) stems.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use symphonia::core::{
+    io::BufReader,
+    meta::{MetadataBuilder, MetadataRevision, StandardTagKey, StandardVisualKey, Value},
+};
+use symphonia_metadata::{id3v2, riff};
+
+use crate::types::{AudioMetadata, Picture};
+
+/// FLAC metadata block types.
+pub(crate) const FLAC_VORBIS_COMMENT: u8 = 4;
+pub(crate) const FLAC_PICTURE: u8 = 6;
+/// FLAC metadata block lengths are 24-bit.
+const FLAC_MAX_BLOCK_LEN: usize = (1 << 24) - 1;
+/// ID3v2.4 tag and frame sizes are 28-bit syncsafe integers.
+const ID3_MAX_LEN: usize = (1 << 28) - 1;
+
+/// Length of a version 1 `bext` chunk, and where its 64-bit time reference
+/// and version live.
+const BEXT_LEN: usize = 602;
+const BEXT_TIME_REFERENCE: usize = 338;
+const BEXT_VERSION: usize = 346;
+
+/// Vorbis comment carrying the BWF time reference in FLAC files.
+const TIME_REFERENCE_TAG: &str = "TIME_REFERENCE";
+
+/// Vorbis comment names for the tags that have a dedicated field in ID3 or
+/// RIFF INFO, so the same tag reads back under the same name from any format.
+const KNOWN_TAGS: &[(StandardTagKey, &str)] = &[
+    (StandardTagKey::TrackTitle, "TITLE"),
+    (StandardTagKey::Artist, "ARTIST"),
+    (StandardTagKey::Album, "ALBUM"),
+    (StandardTagKey::AlbumArtist, "ALBUMARTIST"),
+    (StandardTagKey::Date, "DATE"),
+    (StandardTagKey::Genre, "GENRE"),
+    (StandardTagKey::TrackNumber, "TRACKNUMBER"),
+    (StandardTagKey::DiscNumber, "DISCNUMBER"),
+    (StandardTagKey::IdentIsrc, "ISRC"),
+    (StandardTagKey::Composer, "COMPOSER"),
+    (StandardTagKey::Copyright, "COPYRIGHT"),
+    (StandardTagKey::Comment, "COMMENT"),
+    (StandardTagKey::Bpm, "BPM"),
+];
+
+/// ID3v2.4 text frames for [`KNOWN_TAGS`]; `COMMENT` goes into `COMM` and
+/// everything else into `TXXX`.
+const ID3_FRAMES: &[(&str, &[u8; 4])] = &[
+    ("TITLE", b"TIT2"),
+    ("ARTIST", b"TPE1"),
+    ("ALBUM", b"TALB"),
+    ("ALBUMARTIST", b"TPE2"),
+    ("DATE", b"TDRC"),
+    ("GENRE", b"TCON"),
+    ("TRACKNUMBER", b"TRCK"),
+    ("DISCNUMBER", b"TPOS"),
+    ("ISRC", b"TSRC"),
+    ("COMPOSER", b"TCOM"),
+    ("COPYRIGHT", b"TCOP"),
+    ("BPM", b"TBPM"),
+];
+
+/// RIFF INFO fields written for players that do not read ID3 in WAV.
+const INFO_FIELDS: &[(&str, &[u8; 4])] = &[
+    ("TITLE", b"INAM"),
+    ("ARTIST", b"IART"),
+    ("ALBUM", b"IPRD"),
+    ("DATE", b"ICRD"),
+    ("GENRE", b"IGNR"),
+    ("COMPOSER", b"IMUS"),
+    ("COPYRIGHT", b"ICOP"),
+    ("COMMENT", b"ICMT"),
+];
+
+/// Tags describing how the source was encoded or how loud it is, which do not
+/// hold for stems.
+fn is_encoding_tag(key: StandardTagKey) -> bool {
+    matches!(
+        key,
+        StandardTagKey::Encoder
+            | StandardTagKey::EncoderSettings
+            | StandardTagKey::EncodedBy
+            | StandardTagKey::EncodingDate
+            | StandardTagKey::ReplayGainAlbumGain
+            | StandardTagKey::ReplayGainAlbumPeak
+            | StandardTagKey::ReplayGainTrackGain
+            | StandardTagKey::ReplayGainTrackPeak
+    )
+}
+
+fn picture_type(usage: StandardVisualKey) -> u8 {
+    (1..=20)
+        .find(|&apic| id3v2::util::apic_picture_type_to_visual_key(apic) == Some(usage))
+        .unwrap_or(0) as u8
+}
+
+/// A time reference of `samples` at `from_rate`, in samples at `to_rate`.
+pub(crate) fn rescale(samples: u64, from_rate: u32, to_rate: u32) -> u64 {
+    if from_rate == to_rate || from_rate == 0 {
+        samples
+    } else {
+        (samples as f64 * to_rate as f64 / from_rate as f64).round() as u64
+    }
+}
+
+/// Remove the iTunes gapless tag (`iTunSMPB`) from `meta` and return the
+/// encoder priming and the number of valid frames it records.
+///
+/// The tag only describes the encoded file, so it must not be copied to the
+/// stems either way.
+pub(crate) fn take_itunes_gapless(meta: &mut AudioMetadata) -> Option<(u64, u64)> {
+    let is_smpb = |key: &str| {
+        key.rsplit(':')
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case("iTunSMPB"))
+    };
+    let value = meta.tags.iter().find(|(k, _)| is_smpb(k))?.1.clone();
+    meta.tags.retain(|(k, _)| !is_smpb(k));
+
+    // " 00000000 <priming> <padding> <valid frames> ...", all hex.
+    let fields: Vec<u64> = value
+        .split_whitespace()
+        .take(4)
+        .map(|field| u64::from_str_radix(field, 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match fields[..] {
+        [_, priming, _, frames] if frames > 0 => Some((priming, frames)),
+        _ => None,
+    }
+}
+
+/// Add the tags and pictures of a symphonia metadata revision to `meta`,
+/// skipping ones it already has.
+pub(crate) fn add_revision(meta: &mut AudioMetadata, rev: &MetadataRevision) {
+    for tag in rev.tags() {
+        if tag.std_key.is_some_and(is_encoding_tag) {
+            continue;
+        }
+        let value = match &tag.value {
+            Value::Binary(_) | Value::Flag => continue,
+            value => value.to_string(),
+        };
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        let key = match KNOWN_TAGS.iter().find(|(k, _)| Some(*k) == tag.std_key) {
+            Some((_, name)) => name,
+            None => tag.key.strip_prefix("TXXX:").unwrap_or(&tag.key),
+        };
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+
+        if key.eq_ignore_ascii_case(TIME_REFERENCE_TAG) {
+            if let Ok(samples) = value.parse() {
+                meta.time_reference.get_or_insert(samples);
+            }
+        } else if !meta
+            .tags
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case(key) && v == value)
+        {
+            meta.tags.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    for visual in rev.visuals() {
+        let picture = Picture {
+            media_type: visual.media_type.clone(),
+            picture_type: visual.usage.map_or(0, picture_type),
+            description: visual
+                .tags
+                .iter()
+                .find(|t| t.std_key == Some(StandardTagKey::Description))
+                .map(|t| t.value.to_string())
+                .unwrap_or_default(),
+            data: visual.data.to_vec(),
+        };
+        if !meta.pictures.contains(&picture) {
+            meta.pictures.push(picture);
+        }
+    }
+}
+
+/// Add the metadata of a RIFF/WAVE file at `path` that symphonia does not
+/// read: LIST/INFO chunks after the audio, `id3 ` chunks and the `bext` time
+/// reference. Other files are left alone.
+pub(crate) fn read_wav_chunks(path: &Path, meta: &mut AudioMetadata) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    if file.read_exact(&mut header).is_err() || &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Ok(());
+    }
+
+    let mut chunk = [0u8; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let id = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        let next = file.stream_position()? + len + (len & 1);
+
+        let mut body = Vec::new();
+        if matches!(&id, b"LIST" | b"id3 " | b"ID3 " | b"bext") {
+            (&mut file).take(len).read_to_end(&mut body)?;
+        }
+        match &id {
+            b"LIST" if body.starts_with(b"INFO") => add_info(meta, &body[4..]),
+            b"id3 " | b"ID3 " => {
+                let mut builder = MetadataBuilder::new();
+                id3v2::read_id3v2(&mut BufReader::new(&body), &mut builder)
+                    .map_err(|e| anyhow!("Invalid ID3 chunk: {}", e))?;
+                add_revision(meta, &builder.metadata());
+            }
+            b"bext" if body.len() >= BEXT_TIME_REFERENCE + 8 => {
+                let mut time_reference = [0u8; 8];
+                time_reference.copy_from_slice(&body[BEXT_TIME_REFERENCE..BEXT_TIME_REFERENCE + 8]);
+                meta.time_reference = Some(u64::from_le_bytes(time_reference));
+            }
+            _ => {}
+        }
+        file.seek(SeekFrom::Start(next))?;
+    }
+    Ok(())
+}
+
+fn add_info(meta: &mut AudioMetadata, mut body: &[u8]) {
+    let mut builder = MetadataBuilder::new();
+    while body.len() >= 8 {
+        let id = [body[0], body[1], body[2], body[3]];
+        let len = u32::from_le_bytes([body[4], body[5], body[6], body[7]]) as usize;
+        let value = &body[8..(8 + len).min(body.len())];
+        builder.add_tag(riff::parse(id, value));
+        body = &body[(8 + len + (len & 1)).min(body.len())..];
+    }
+    add_revision(meta, &builder.metadata());
+}
+
+/// Every value of each tag key in `meta`, keys in order of first appearance.
+fn grouped_tags(meta: &AudioMetadata) -> Vec<(&str, Vec<&str>)> {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (key, value) in &meta.tags {
+        match groups.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            Some((_, values)) => values.push(value),
+            None => groups.push((key, vec![value])),
+        }
+    }
+    groups
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn info_chunk(meta: &AudioMetadata) -> Option<Vec<u8>> {
+    let mut body = b"INFO".to_vec();
+    for (key, id) in INFO_FIELDS {
+        if let Some(value) = meta.get(key) {
+            let mut text = value.as_bytes().to_vec();
+            text.push(0);
+            push_chunk(&mut body, id, &text);
+        }
+    }
+    (body.len() > 4).then_some(body)
+}
+
+fn syncsafe(n: usize) -> [u8; 4] {
+    [
+        (n >> 21) as u8 & 0x7f,
+        (n >> 14) as u8 & 0x7f,
+        (n >> 7) as u8 & 0x7f,
+        n as u8 & 0x7f,
+    ]
+}
+
+fn push_id3_frame(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    if body.len() > ID3_MAX_LEN {
+        return;
+    }
+    out.extend_from_slice(id);
+    out.extend_from_slice(&syncsafe(body.len()));
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(body);
+}
+
+/// An ID3v2.4 tag with UTF-8 text frames. Repeated tags become one frame
+/// with NUL-separated values, as v2.4 allows.
+fn id3v2_tag(meta: &AudioMetadata) -> Option<Vec<u8>> {
+    const UTF8: u8 = 3;
+
+    let mut frames = Vec::new();
+    for (key, values) in grouped_tags(meta) {
+        let text = values.join("\0");
+        let mut body = vec![UTF8];
+        if key.eq_ignore_ascii_case("COMMENT") {
+            body.extend_from_slice(b"eng\0");
+            body.extend_from_slice(text.as_bytes());
+            push_id3_frame(&mut frames, b"COMM", &body);
+        } else if let Some((_, id)) = ID3_FRAMES.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            body.extend_from_slice(text.as_bytes());
+            push_id3_frame(&mut frames, id, &body);
+        } else {
+            body.extend_from_slice(key.as_bytes());
+            body.push(0);
+            body.extend_from_slice(text.as_bytes());
+            push_id3_frame(&mut frames, b"TXXX", &body);
+        }
+    }
+    for picture in &meta.pictures {
+        let mut body = vec![UTF8];
+        body.extend_from_slice(picture.media_type.as_bytes());
+        body.push(0);
+        body.push(picture.picture_type);
+        body.extend_from_slice(picture.description.as_bytes());
+        body.push(0);
+        body.extend_from_slice(&picture.data);
+        push_id3_frame(&mut frames, b"APIC", &body);
+    }
+    if frames.is_empty() || frames.len() > ID3_MAX_LEN {
+        return None;
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(frames.len()));
+    tag.extend_from_slice(&frames);
+    Some(tag)
+}
+
+fn bext_chunk(time_reference: u64) -> Vec<u8> {
+    let mut body = vec![0u8; BEXT_LEN];
+    body[BEXT_TIME_REFERENCE..BEXT_TIME_REFERENCE + 8]
+        .copy_from_slice(&time_reference.to_le_bytes());
+    body[BEXT_VERSION..BEXT_VERSION + 2].copy_from_slice(&1u16.to_le_bytes());
+    body
+}
+
+/// Append chunks carrying `meta` to the finished WAV file at `path` and
+/// update its RIFF size.
+pub(crate) fn append_wav_chunks(path: &Path, meta: &AudioMetadata) -> Result<()> {
+    if meta.is_empty() {
+        return Ok(());
+    }
+
+    let mut chunks = Vec::new();
+    if let Some(time_reference) = meta.time_reference {
+        push_chunk(&mut chunks, b"bext", &bext_chunk(time_reference));
+    }
+    if let Some(info) = info_chunk(meta) {
+        push_chunk(&mut chunks, b"LIST", &info);
+    }
+    if let Some(id3) = id3v2_tag(meta) {
+        push_chunk(&mut chunks, b"id3 ", &id3);
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut len = file.seek(SeekFrom::End(0))?;
+    // Chunks start on even offsets; the data chunk may have ended on an odd one.
+    if len % 2 == 1 {
+        file.write_all(&[0])?;
+        len += 1;
+    }
+    let riff_len = u32::try_from(len + chunks.len() as u64 - 8)
+        .map_err(|_| anyhow!("WAV file too large for its tags: {}", path.display()))?;
+    file.write_all(&chunks)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_len.to_le_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// FLAC metadata blocks, as `(block type, body)`, carrying `meta`: a Vorbis
+/// comment block and a picture block per picture.
+pub(crate) fn flac_blocks(meta: &AudioMetadata) -> Vec<(u8, Vec<u8>)> {
+    let mut blocks = Vec::new();
+
+    let mut comments: Vec<String> = meta
+        .tags
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    if let Some(time_reference) = meta.time_reference {
+        comments.push(format!("{}={}", TIME_REFERENCE_TAG, time_reference));
+    }
+    if !comments.is_empty() {
+        let vendor = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+        let mut body = Vec::new();
+        body.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        body.extend_from_slice(vendor.as_bytes());
+        body.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in &comments {
+            body.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            body.extend_from_slice(comment.as_bytes());
+        }
+        if body.len() <= FLAC_MAX_BLOCK_LEN {
+            blocks.push((FLAC_VORBIS_COMMENT, body));
+        }
+    }
+
+    for picture in &meta.pictures {
+        let mut body = Vec::new();
+        body.extend_from_slice(&u32::from(picture.picture_type).to_be_bytes());
+        for text in [&picture.media_type, &picture.description] {
+            body.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            body.extend_from_slice(text.as_bytes());
+        }
+        // Width, height, colour depth and palette size: unknown.
+        body.extend_from_slice(&[0; 16]);
+        body.extend_from_slice(&(picture.data.len() as u32).to_be_bytes());
+        body.extend_from_slice(&picture.data);
+        if body.len() <= FLAC_MAX_BLOCK_LEN {
+            blocks.push((FLAC_PICTURE, body));
+        }
+    }
+
+    blocks
+}
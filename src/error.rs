@@ -19,6 +19,9 @@ pub enum StemError {
 
     #[error("manifest error: {0}")]
     Manifest(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
 }
 
 // --- Implement From conversions for common errors ---
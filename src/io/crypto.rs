@@ -2,7 +2,8 @@ use crate::error::Result;
 use sha2::{Digest, Sha256};
 use std::{fs::File, io::Read, path::Path};
 
-pub fn verify_sha256(path: &Path, expected_hex: &str) -> Result<bool> {
+/// Hex-encoded SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String> {
     let mut f = File::open(path)?;
     let mut hasher = Sha256::new();
     let mut buf = [0u8; 64 * 1024];
@@ -13,6 +14,10 @@ pub fn verify_sha256(path: &Path, expected_hex: &str) -> Result<bool> {
         }
         hasher.update(&buf[..n]);
     }
-    let got = hex::encode(hasher.finalize());
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn verify_sha256(path: &Path, expected_hex: &str) -> Result<bool> {
+    let got = sha256_file(path)?;
     Ok(got.eq_ignore_ascii_case(expected_hex))
 }
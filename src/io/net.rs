@@ -1,4 +1,8 @@
-use crate::{error::Result, io::progress::emit_download_progress};
+use crate::{
+    error::{Result, StemError},
+    io::progress::emit_download_progress,
+    types::CancelToken,
+};
 use reqwest::blocking::Client;
 use std::{
     fs,
@@ -17,8 +21,24 @@ pub fn http_client() -> Client {
 }
 
 pub fn download_with_progress(client: &Client, url: &str, dest: &Path) -> Result<()> {
+    download_with_progress_cancellable(client, url, dest, &CancelToken::default())
+}
+
+/// Like [`download_with_progress`], but checks `cancel` between reads. A
+/// cancelled download removes its partial file and returns
+/// [`StemError::Cancelled`].
+pub fn download_with_progress_cancellable(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    cancel: &CancelToken,
+) -> Result<()> {
     let tmp = dest.with_extension("part");
 
+    if cancel.is_cancelled() {
+        return Err(StemError::Cancelled);
+    }
+
     let mut resp = client.get(url).send()?.error_for_status()?; // both now convert automatically
 
     let total = resp.content_length().unwrap_or(0);
@@ -29,6 +49,11 @@ pub fn download_with_progress(client: &Client, url: &str, dest: &Path) -> Result
     let mut downloaded: u64 = 0;
     let mut buf = [0u8; 64 * 1024];
     loop {
+        if cancel.is_cancelled() {
+            drop(file);
+            fs::remove_file(&tmp).ok();
+            return Err(StemError::Cancelled);
+        }
         let n = resp.read(&mut buf)?;
         if n == 0 {
             break;
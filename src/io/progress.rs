@@ -9,10 +9,25 @@ static SPLIT_PROGRESS_CB: OnceLock<Mutex<Option<Box<dyn Fn(SplitProgress) + Send
 #[derive(Debug, Clone, serde::Serialize)]
 pub enum SplitProgress {
     Stage(&'static str),
+    /// A batch split started on input `index` (1-based) of `total`.
+    FileStarted {
+        input: String,
+        index: usize,
+        total: usize,
+    },
+    /// A batch split finished input `index`; `error` is set if it failed.
+    FileFinished {
+        input: String,
+        index: usize,
+        total: usize,
+        error: Option<String>,
+    },
     Chunks {
         done: usize,
         total: usize,
         percent: f32,
+        /// Windows skipped so far because their input was silent.
+        silent_windows: usize,
     },
     Writing {
         stem: String,
@@ -3,10 +3,16 @@ mod types;
 
 pub mod core {
     pub mod audio;
+    pub(crate) mod checkpoint;
     pub mod dsp;
     pub mod engine;
     pub(crate) mod ep;
+    pub(crate) mod flac;
+    pub mod gain;
+    pub mod meter;
+    pub mod resample;
     pub mod splitter;
+    pub(crate) mod tags;
 }
 
 pub mod model {
@@ -23,12 +29,19 @@ pub mod io {
 }
 
 // Public API
-pub use crate::core::splitter::split_file;
+pub use crate::core::audio::list_tracks;
+pub use crate::core::splitter::{split_audio, split_file, split_files};
+pub use crate::error::StemError;
 pub use crate::io::progress::{
     set_download_progress_callback, set_split_progress_callback, SplitProgress,
 };
 pub use crate::model::model_manager::{ensure_model, ModelHandle};
-pub use crate::types::{AudioData, ModelManifest, SplitOptions, SplitResult};
+pub use crate::types::{
+    AudioData, AudioMetadata, AudioTrack, BatchItem, BatchResult, CancelToken, ComplementMethod,
+    DecodeDamage, GainStaging, MixConsistency, MixResidual, ModelManifest, MultichannelMode,
+    OutputFormat, OverlapWindow, Picture, SplitOptions, SplitResult, StemBuffers, StemFile,
+    StemStats, TrackSelector,
+};
 
 pub fn prepare_model(model_name: &str, manifest_url_override: Option<&str>) -> error::Result<()> {
     let handle = ensure_model(model_name, manifest_url_override)?;
@@ -2,11 +2,11 @@ use crate::{
     error::{Result, StemError},
     io::{
         crypto::verify_sha256,
-        net::{download_with_progress, http_client},
+        net::{download_with_progress_cancellable, http_client},
         paths::models_cache_dir,
     },
     model::registry::resolve_manifest_url,
-    types::ModelManifest,
+    types::{CancelToken, ModelManifest},
 };
 
 use std::{fs, path::PathBuf};
@@ -17,6 +17,16 @@ pub struct ModelHandle {
 }
 
 pub fn ensure_model(model_name: &str, manifest_url_override: Option<&str>) -> Result<ModelHandle> {
+    ensure_model_cancellable(model_name, manifest_url_override, &CancelToken::default())
+}
+
+/// Like [`ensure_model`], but a model download stops early with
+/// [`StemError::Cancelled`] once `cancel` is triggered.
+pub fn ensure_model_cancellable(
+    model_name: &str,
+    manifest_url_override: Option<&str>,
+    cancel: &CancelToken,
+) -> Result<ModelHandle> {
     let manifest_url = manifest_url_override
         .map(|s| s.to_string())
         .unwrap_or_else(|| resolve_manifest_url(model_name).expect("resolve_manifest_url failed"));
@@ -45,7 +55,7 @@ pub fn ensure_model(model_name: &str, manifest_url_override: Option<&str>) -> Re
 
     let need_download = !matches!(verify_sha256(&local_path, &a.sha256), Ok(true));
     if need_download {
-        download_with_progress(&client, &a.url, &local_path)?;
+        download_with_progress_cancellable(&client, &a.url, &local_path, cancel)?;
         if !verify_sha256(&local_path, &a.sha256)? {
             return Err(StemError::Checksum {
                 path: local_path.display().to_string(),
@@ -1,17 +1,333 @@
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct AudioData {
     pub samples: Vec<f32>,
     pub sample_rate: u32,
     pub channels: u16,
+    /// Tags read from the source file, written back by `write_audio`.
+    pub metadata: AudioMetadata,
+    /// Packets that could not be decoded and were left out of `samples`.
+    pub damage: DecodeDamage,
+}
+
+/// Corrupt packets skipped while decoding an input.
+///
+/// The decoder drops a packet it cannot decode and carries on with the next
+/// one, so a damaged file still decodes to the end, shorter by
+/// `dropped_frames`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeDamage {
+    pub skipped_packets: u64,
+    /// Frames the skipped packets would have decoded to, at the source rate.
+    pub dropped_frames: u64,
+}
+
+impl DecodeDamage {
+    pub fn is_clean(&self) -> bool {
+        self.skipped_packets == 0
+    }
+}
+
+/// Tags, cover art and timecode of an audio file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioMetadata {
+    /// Text tags in file order. Well-known tags use Vorbis comment names
+    /// (`TITLE`, `ARTIST`, `ALBUM`, `ISRC`, ...) whatever the source format;
+    /// others keep the key they were stored under.
+    pub tags: Vec<(String, String)>,
+    pub pictures: Vec<Picture>,
+    /// BWF `bext` time reference: the first sample's position, in samples
+    /// since midnight.
+    pub time_reference: Option<u64>,
+}
+
+impl AudioMetadata {
+    /// First value of the tag `key`, compared case-insensitively.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replace every value of the tag `key` with `value`.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self
+            .tags
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some(idx) => {
+                self.tags[idx].1 = value;
+                let mut seen = 0;
+                self.tags.retain(|(k, _)| {
+                    let duplicate = k.eq_ignore_ascii_case(key) && seen > 0;
+                    seen += usize::from(k.eq_ignore_ascii_case(key));
+                    !duplicate
+                });
+            }
+            None => self.tags.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.pictures.is_empty() && self.time_reference.is_none()
+    }
+}
+
+/// An embedded picture such as cover art.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Picture {
+    /// MIME type of `data`, e.g. `image/jpeg`.
+    pub media_type: String,
+    /// ID3/FLAC picture type; 3 is the front cover.
+    pub picture_type: u8,
+    pub description: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapWindow {
+    /// Demucs-style triangle spanning the whole window.
+    #[default]
+    Triangular,
+    /// Flat top with linear fades across the overlapping region only.
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplementMethod {
+    /// Sum of every other source the model produced.
+    #[default]
+    Sum,
+    /// The input mixture minus the selected source.
+    Minus,
+}
+
+/// How the difference between the input mixture and the sum of the separated
+/// sources is spread back over the sources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MixConsistency {
+    /// Leave the sources as the model produced them.
+    #[default]
+    Off,
+    /// Every source gets the same share of the difference.
+    Equal,
+    /// Each source gets a share proportional to its energy in the window, so
+    /// quiet sources pick up little of it.
+    Energy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// 16-bit PCM WAV, TPDF-dithered.
+    #[default]
+    Wav16,
+    /// 24-bit PCM WAV.
+    Wav24,
+    /// 32-bit float WAV; keeps peaks above full scale.
+    WavF32,
+    /// 16-bit FLAC, TPDF-dithered.
+    Flac16,
+    /// 24-bit FLAC.
+    Flac24,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav16 | OutputFormat::Wav24 | OutputFormat::WavF32 => "wav",
+            OutputFormat::Flac16 | OutputFormat::Flac24 => "flac",
+        }
+    }
+}
+
+/// Level handling applied to stems before they are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GainStaging {
+    /// Write stems as separated; integer formats clip anything beyond full
+    /// scale.
+    #[default]
+    Off,
+    /// One gain for every stem, lowering them until the loudest true peak is
+    /// at `ceiling_db` dBTP. Keeps the balance between stems and never boosts.
+    Common { ceiling_db: f32 },
+    /// Scale each stem so its true peak is at `peak_db` dBTP.
+    NormalizePeak { peak_db: f32 },
+    /// Scale each stem to `lufs` integrated loudness, capped so its true peak
+    /// stays at or below 0 dBTP.
+    NormalizeLoudness { lufs: f32 },
+    /// Lookahead limiter on each stem keeping samples below `ceiling_db`
+    /// dBFS. The gain varies over time, so it cannot be undone.
+    Limit { ceiling_db: f32 },
+}
+
+/// How inputs with more than two channels (5.1, 7.1, ...) are separated.
+/// `Downmix` and `PerPair` separate mono and stereo inputs as they are.
+///
+/// Channels are taken to be in WAV order (`L R C LFE Ls Rs` for 5.1,
+/// `L R C LFE Lb Rb Ls Rs` for 7.1), which is how the decoder delivers them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultichannelMode {
+    /// Downmix to stereo with ITU-R BS.775 coefficients (centre and surrounds
+    /// at -3 dB, LFE dropped) and separate that; stems are stereo.
+    #[default]
+    Downmix,
+    /// Separate every left/right pair on its own and every other channel
+    /// (centre, LFE, back centre) as mono; stems keep the input's channels.
+    PerPair,
+    /// Separate only these two channels, counted from 0; stems are stereo.
+    /// Applies to mono and stereo inputs too, and a channel the input does
+    /// not have is an error.
+    Pair { left: usize, right: usize },
+}
+
+/// Which audio track of the input to read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSelector {
+    /// The container's default track: the first one with a known codec.
+    #[default]
+    Default,
+    /// Position among the file's audio tracks, as in [`AudioTrack::index`].
+    Index(usize),
+    /// First audio track tagged with this language (e.g. `eng`), compared
+    /// case-insensitively.
+    Language(String),
+}
+
+impl std::str::FromStr for TrackSelector {
+    type Err = std::convert::Infallible;
+
+    /// `default`, an index or a language code.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(if s.eq_ignore_ascii_case("default") {
+            TrackSelector::Default
+        } else if let Ok(index) = s.parse() {
+            TrackSelector::Index(index)
+        } else {
+            TrackSelector::Language(s.to_string())
+        })
+    }
+}
+
+/// An audio track of an input file, as listed by
+/// [`list_tracks`](crate::list_tracks).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioTrack {
+    /// Position among the file's audio tracks; what
+    /// [`TrackSelector::Index`] selects.
+    pub index: usize,
+    /// Track number or ID in the container.
+    pub id: u32,
+    /// Short codec name (`aac`, `flac`, `pcm_s16le`, ...); `None` when there
+    /// is no decoder for it.
+    pub codec: Option<String>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub language: Option<String>,
+    /// Whether [`TrackSelector::Default`] picks this track.
+    pub default: bool,
+}
+
+/// Shared flag for stopping an in-flight split or model download.
+///
+/// Clones share the same flag, so keep one and put a clone in
+/// [`SplitOptions::cancel`].
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SplitOptions {
     pub output_dir: String,
     pub model_name: String,
     pub manifest_url_override: Option<String>,
+    /// Resample stems back to the input's sample rate instead of writing them
+    /// at the model rate. Default `false`.
+    pub resample_to_source_rate: bool,
+    /// Fraction of each inference window shared with the next one; `None`
+    /// (the default) uses the hop from the model manifest.
+    pub overlap: Option<f32>,
+    /// Weighting used to blend overlapping windows. Default `Triangular`.
+    pub overlap_window: OverlapWindow,
+    /// Randomly shifted passes averaged per window; 0 (the default) disables
+    /// shifting.
+    pub shifts: usize,
+    /// Also average a polarity-inverted pass. Default `false`.
+    pub flip_polarity: bool,
+    /// Also average a left-right swapped pass. Default `false`.
+    pub flip_channels: bool,
+    /// Seed for the shift offsets; `None` (the default) picks a random seed
+    /// per split.
+    pub seed: Option<u64>,
+    /// Only write these stems; empty (the default) writes every stem.
+    pub stems: Vec<String>,
+    /// Two-stem mode: write only this stem and `no_<stem>`. Default `None`.
+    pub two_stems: Option<String>,
+    /// How `no_<stem>` is built in two-stem mode. Default `Sum`.
+    pub complement: ComplementMethod,
+    /// File format and bit depth of the written stems. Default `Wav16`.
+    pub output_format: OutputFormat,
+    /// Seconds into the input where splitting starts; `None` (the default)
+    /// starts at the beginning.
+    pub start: Option<f64>,
+    /// Seconds into the input where splitting stops, clamped to its length;
+    /// `None` (the default) runs to the end.
+    pub end: Option<f64>,
+    /// Windows stacked into each model call; `None` (the default) picks one
+    /// for the execution provider. Models without a dynamic batch axis
+    /// ignore it.
+    pub batch_size: Option<usize>,
+    /// Keep a checkpoint next to the stems so an interrupted `split_file`
+    /// resumes from its last finished chunk. Default `false`.
+    pub checkpoint: bool,
+    /// Level handling applied to the stems before writing. Default `Off`.
+    pub gain: GainStaging,
+    /// EM iterations of the Wiener filter refining each window's stems
+    /// against the mixture; 0 (the default) disables it.
+    pub wiener_iterations: usize,
+    /// How the input minus the summed sources is spread back over the
+    /// sources. Default `Off`.
+    pub consistency: MixConsistency,
+    /// Windows whose input peaks below this many dBFS are written as
+    /// silence without running the model; `None` (the default) runs every
+    /// window.
+    pub skip_silence: Option<f32>,
+    /// Audio track of the input to split. Default `TrackSelector::Default`.
+    pub track: TrackSelector,
+    /// How inputs with more than two channels are separated. Default
+    /// `Downmix`.
+    pub multichannel: MultichannelMode,
+    #[serde(skip)]
+    pub cancel: CancelToken,
 }
 
 impl Default for SplitOptions {
@@ -20,16 +336,134 @@ impl Default for SplitOptions {
             output_dir: ".".into(),
             model_name: "htdemucs_ort_v1".into(),
             manifest_url_override: None,
+            resample_to_source_rate: false,
+            overlap: None,
+            overlap_window: OverlapWindow::Triangular,
+            shifts: 0,
+            flip_polarity: false,
+            flip_channels: false,
+            seed: None,
+            stems: Vec::new(),
+            two_stems: None,
+            complement: ComplementMethod::Sum,
+            output_format: OutputFormat::Wav16,
+            start: None,
+            end: None,
+            batch_size: None,
+            checkpoint: false,
+            gain: GainStaging::Off,
+            wiener_iterations: 0,
+            consistency: MixConsistency::Off,
+            skip_silence: None,
+            track: TrackSelector::Default,
+            multichannel: MultichannelMode::Downmix,
+            cancel: CancelToken::default(),
         }
     }
 }
 
+/// Levels of a written stem, measured on the samples handed to the writer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StemStats {
+    /// Highest absolute sample value (1.0 = full scale).
+    pub sample_peak: f32,
+    /// Highest peak between samples as well, estimated with 4x oversampling
+    /// (ITU-R BS.1770).
+    pub true_peak: f32,
+    /// RMS over all samples of all channels.
+    pub rms: f32,
+    /// Integrated loudness in LUFS (EBU R128), with surround channels weighted
+    /// 1.41 and the LFE left out; `-inf` for silence or stems shorter than
+    /// 400 ms.
+    pub integrated_lufs: f32,
+    /// Samples beyond full scale, clamped when quantizing to an integer
+    /// format. Dither alone does not count.
+    pub clipped_samples: u64,
+}
+
+/// How far the sum of all model sources is from the input mixture over the
+/// split range, whichever stems were written.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MixResidual {
+    /// RMS of the mixture minus the summed sources.
+    pub rms: f32,
+    /// Residual energy relative to the mixture in dB; `-inf` if the sources
+    /// add up exactly.
+    pub relative_db: f32,
+}
+
 #[derive(Clone, Debug)]
+pub struct StemFile {
+    pub path: String,
+    /// Levels of the written samples, after gain staging.
+    pub stats: StemStats,
+    /// Static gain applied by [`SplitOptions::gain`]; apply its negative to
+    /// get the separated levels back. Zero when off or limiting.
+    pub gain_db: f32,
+}
+
+/// Written stems keyed by name, in the order the model produces them.
+#[derive(Clone, Debug, Default)]
 pub struct SplitResult {
-    pub vocals_path: String,
-    pub drums_path: String,
-    pub bass_path: String,
-    pub other_path: String,
+    pub stems: IndexMap<String, StemFile>,
+    pub mix_residual: MixResidual,
+    /// Inference windows the input was split into.
+    pub windows: usize,
+    /// Windows whose input was silent; they were written as silence without
+    /// running the model.
+    pub silent_windows: usize,
+    /// Corrupt packets skipped while decoding the input.
+    pub decode_damage: DecodeDamage,
+}
+
+impl SplitResult {
+    pub fn path(&self, stem: &str) -> Option<&str> {
+        self.stems.get(stem).map(|s| s.path.as_str())
+    }
+}
+
+/// Outcome of one input of a batch split.
+#[derive(Debug)]
+pub struct BatchItem {
+    pub input: String,
+    pub result: crate::error::Result<SplitResult>,
+}
+
+/// Per-input outcomes of [`split_files`](crate::split_files), in input order.
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    pub items: Vec<BatchItem>,
+}
+
+impl BatchResult {
+    pub fn succeeded(&self) -> impl Iterator<Item = (&str, &SplitResult)> {
+        self.items
+            .iter()
+            .filter_map(|item| Some((item.input.as_str(), item.result.as_ref().ok()?)))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&str, &crate::error::StemError)> {
+        self.items
+            .iter()
+            .filter_map(|item| Some((item.input.as_str(), item.result.as_ref().err()?)))
+    }
+}
+
+/// In-memory stems keyed by name, in the order the model produces them.
+///
+/// Each stem is interleaved. It is stereo for mono and stereo inputs and with
+/// [`MultichannelMode::Downmix`] or [`MultichannelMode::Pair`]; with
+/// [`MultichannelMode::PerPair`] a multichannel input's stems keep its
+/// channel count and order.
+#[derive(Clone, Debug, Default)]
+pub struct StemBuffers {
+    pub stems: IndexMap<String, AudioData>,
+}
+
+impl StemBuffers {
+    pub fn get(&self, stem: &str) -> Option<&AudioData> {
+        self.stems.get(stem)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -1,10 +1,16 @@
 use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use tempfile::tempdir;
 
 use hound;
-use stem_splitter_core::core::audio::{read_audio, write_audio};
-use stem_splitter_core::AudioData;
+use stem_splitter_core::core::audio::{
+    read_audio, read_audio_track, write_audio, write_audio_with_format, AudioReader, AudioWriter,
+};
+use stem_splitter_core::{
+    list_tracks, AudioData, AudioMetadata, AudioTrack, OutputFormat, Picture, TrackSelector,
+};
 
 fn mono_sine(sample_rate: u32, freq: f32, seconds: f32) -> Vec<f32> {
     let n = (sample_rate as f32 * seconds) as usize;
@@ -32,6 +38,7 @@ fn write_then_read_roundtrip_mono_sine() {
         samples: samples.clone(),
         sample_rate: sr,
         channels: 1,
+        ..Default::default()
     };
 
     write_audio(&path_str, &audio).expect("write_audio failed");
@@ -110,6 +117,7 @@ fn write_audio_clamps_samples_to_16bit() {
         samples: vec![2.0, -2.0, 0.0],
         sample_rate: 44_100,
         channels: 1,
+        ..Default::default()
     };
 
     write_audio(&path_str, &audio).expect("write_audio failed");
@@ -136,6 +144,7 @@ fn write_audio_creates_parent_directories() {
         samples: vec![0.0; 64],
         sample_rate: 22_050,
         channels: 1,
+        ..Default::default()
     };
 
     write_audio(&path_str, &audio).expect("write_audio failed");
@@ -154,3 +163,490 @@ fn read_audio_nonexistent_file_returns_error() {
         "expected an open/read error, got: {msg}"
     );
 }
+
+#[test]
+fn audio_reader_streams_in_blocks() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("stream.wav");
+    let path_str = path.to_string_lossy().to_string();
+
+    let sr = 44_100;
+    let samples = mono_sine(sr, 220.0, 0.5);
+    let audio = AudioData {
+        samples: samples.clone(),
+        sample_rate: sr,
+        channels: 1,
+        ..Default::default()
+    };
+    write_audio(&path_str, &audio).expect("write_audio failed");
+
+    let mut reader = AudioReader::open(&path).expect("open failed");
+    assert_eq!(reader.sample_rate(), sr);
+    assert_eq!(reader.channels(), 1);
+    assert_eq!(reader.n_frames(), Some(samples.len() as u64));
+
+    let mut streamed = Vec::new();
+    let mut blocks = 0;
+    loop {
+        let got = reader.read_frames(1000, &mut streamed).unwrap();
+        if got == 0 {
+            break;
+        }
+        assert!(got <= 1000);
+        blocks += 1;
+    }
+
+    assert_eq!(blocks, samples.len().div_ceil(1000));
+    assert_eq!(streamed, read_audio(&path).unwrap().samples);
+}
+
+#[test]
+fn every_output_format_roundtrips() {
+    let tmp = tempdir().unwrap();
+    let sr = 48_000;
+    // Interleaved stereo, long enough for several FLAC blocks plus a partial one.
+    let mut samples = Vec::new();
+    for s in mono_sine(sr, 330.0, 0.3) {
+        samples.push(s);
+        samples.push(-0.5 * s);
+    }
+    let audio = AudioData {
+        samples,
+        sample_rate: sr,
+        channels: 2,
+        ..Default::default()
+    };
+
+    for (format, ext, bits, tol) in [
+        (OutputFormat::Wav16, "wav", 16, 1e-4),
+        (OutputFormat::Wav24, "wav", 24, 1e-6),
+        (OutputFormat::WavF32, "wav", 32, 0.0),
+        (OutputFormat::Flac16, "flac", 16, 1e-4),
+        (OutputFormat::Flac24, "flac", 24, 1e-6),
+    ] {
+        assert_eq!(format.extension(), ext);
+        let path = tmp.path().join(format!("{format:?}.{ext}"));
+        write_audio_with_format(path.to_str().unwrap(), &audio, format).unwrap();
+
+        let decoded = read_audio(&path).unwrap();
+        assert_eq!(decoded.sample_rate, sr, "{format:?}");
+        assert_eq!(decoded.channels, 2, "{format:?}");
+        assert_eq!(decoded.samples.len(), audio.samples.len(), "{format:?}");
+        for (a, b) in decoded.samples.iter().zip(audio.samples.iter()) {
+            assert!(approx_eq(*a, *b, tol), "{format:?}: {a} vs {b}");
+        }
+
+        if ext == "wav" {
+            let spec = hound::WavReader::open(&path).unwrap().spec();
+            assert_eq!(spec.bits_per_sample, bits, "{format:?}");
+        }
+    }
+}
+
+#[test]
+fn float_wav_keeps_peaks_above_full_scale() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("hot.wav");
+    let audio = AudioData {
+        samples: vec![1.5, -2.0, 0.25],
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+
+    write_audio_with_format(path.to_str().unwrap(), &audio, OutputFormat::WavF32).unwrap();
+    assert_eq!(read_audio(&path).unwrap().samples, audio.samples);
+}
+
+#[test]
+fn audio_writer_counts_clipped_samples() {
+    let tmp = tempdir().unwrap();
+    let samples = [0.5, 1.5, -2.0, 0.9, -0.25, 1.0001];
+
+    for (format, clipped) in [
+        (OutputFormat::Wav24, 3),
+        (OutputFormat::Flac16, 3),
+        (OutputFormat::WavF32, 0),
+    ] {
+        let path = tmp
+            .path()
+            .join(format!("hot_{format:?}.{}", format.extension()));
+        let mut writer = AudioWriter::create(&path, 44_100, 2, format).unwrap();
+        writer.write_samples(&samples).unwrap();
+        assert_eq!(writer.clipped_samples(), clipped, "{format:?}");
+        writer.finalize().unwrap();
+    }
+}
+
+#[test]
+fn dither_on_full_scale_samples_is_not_counted_as_clipping() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("full_scale.wav");
+    // 441 Hz puts a sample on every crest, right at full scale.
+    let samples: Vec<f32> = (0..44_100)
+        .map(|i| (2.0 * PI * 441.0 * i as f32 / 44_100.0).sin())
+        .collect();
+
+    let mut writer = AudioWriter::create(&path, 44_100, 1, OutputFormat::Wav16).unwrap();
+    writer.write_samples(&samples).unwrap();
+    assert_eq!(writer.clipped_samples(), 0);
+    writer.finalize().unwrap();
+}
+
+#[test]
+fn sixteen_bit_output_is_dithered() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("quiet.wav");
+    // A DC level of 0.3 LSB would truncate to silence without dither.
+    let level = 0.3 / i16::MAX as f32;
+    let audio = AudioData {
+        samples: vec![level; 20_000],
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+    write_audio(path.to_str().unwrap(), &audio).unwrap();
+
+    let codes: Vec<i16> = hound::WavReader::open(&path)
+        .unwrap()
+        .into_samples::<i16>()
+        .map(Result::unwrap)
+        .collect();
+    assert!(codes.iter().all(|c| (-1..=2).contains(c)));
+    let mean = codes.iter().map(|&c| c as f64).sum::<f64>() / codes.len() as f64;
+    assert!((mean - 0.3).abs() < 0.05, "mean {mean}");
+}
+
+#[test]
+fn stems_get_independent_but_reproducible_dither() {
+    let tmp = tempdir().unwrap();
+    let level = 0.3 / i16::MAX as f32;
+    let audio = AudioData {
+        samples: vec![level; 20_000],
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+    let write = |path: &Path| {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_audio(path.to_str().unwrap(), &audio).unwrap();
+        hound::WavReader::open(path)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect::<Vec<i16>>()
+    };
+
+    let vocals = write(&tmp.path().join("a/song_vocals.wav"));
+    let drums = write(&tmp.path().join("a/song_drums.wav"));
+    let differing = vocals.iter().zip(&drums).filter(|(a, b)| a != b).count();
+    assert!(
+        differing > vocals.len() / 4,
+        "{differing} differing samples"
+    );
+
+    // The same stem exported again elsewhere is bit-identical.
+    assert_eq!(write(&tmp.path().join("b/song_vocals.wav")), vocals);
+}
+
+#[test]
+fn audio_reader_seeks_to_exact_frames() {
+    let tmp = tempdir().unwrap();
+    let sr = 44_100;
+    let mut samples = Vec::new();
+    for s in mono_sine(sr, 250.0, 1.0) {
+        samples.push(s);
+        samples.push(-s);
+    }
+    let audio = AudioData {
+        samples,
+        sample_rate: sr,
+        channels: 2,
+        ..Default::default()
+    };
+
+    for format in [OutputFormat::Wav16, OutputFormat::Flac16] {
+        let path = tmp.path().join(format!("seek.{}", format.extension()));
+        write_audio_with_format(path.to_str().unwrap(), &audio, format).unwrap();
+        let all = read_audio(&path).unwrap().samples;
+
+        let mut reader = AudioReader::open(&path).unwrap();
+        for frame in [30_000usize, 5_000, 12_345] {
+            reader.seek(frame as u64).unwrap();
+            let mut got = Vec::new();
+            assert_eq!(reader.read_frames(1000, &mut got).unwrap(), 1000);
+            assert_eq!(
+                got,
+                all[frame * 2..(frame + 1000) * 2],
+                "{format:?} @ {frame}"
+            );
+        }
+
+        reader.seek(10 * sr as u64).unwrap();
+        let mut got = Vec::new();
+        assert_eq!(reader.read_frames(1000, &mut got).unwrap(), 0, "{format:?}");
+    }
+}
+
+fn tagged_metadata() -> AudioMetadata {
+    AudioMetadata {
+        tags: vec![
+            ("TITLE".into(), "Señorita".into()),
+            ("ARTIST".into(), "The Testers".into()),
+            ("ALBUM".into(), "Fixtures".into()),
+            ("ISRC".into(), "USRC17607839".into()),
+            ("MOOD".into(), "calm".into()),
+        ],
+        pictures: vec![Picture {
+            media_type: "image/png".into(),
+            picture_type: 3,
+            description: "cover".into(),
+            data: b"\x89PNG not really a png".to_vec(),
+        }],
+        time_reference: Some(158_760_000),
+    }
+}
+
+#[test]
+fn tags_pictures_and_timecode_roundtrip() {
+    let tmp = tempdir().unwrap();
+    // An odd number of 24-bit mono frames leaves the data chunk at an odd
+    // length, which the appended chunks have to be padded past.
+    let audio = AudioData {
+        samples: mono_sine(44_100, 440.0, 0.1)[..4_409].to_vec(),
+        sample_rate: 44_100,
+        channels: 1,
+        metadata: tagged_metadata(),
+        ..Default::default()
+    };
+
+    for format in [
+        OutputFormat::Wav16,
+        OutputFormat::Wav24,
+        OutputFormat::WavF32,
+        OutputFormat::Flac16,
+        OutputFormat::Flac24,
+    ] {
+        let path = tmp
+            .path()
+            .join(format!("tagged.{:?}.{}", format, format.extension()));
+        write_audio_with_format(path.to_str().unwrap(), &audio, format).unwrap();
+        let decoded = read_audio(&path).unwrap();
+
+        assert_eq!(decoded.metadata, audio.metadata, "{format:?}");
+        assert_eq!(decoded.samples.len(), audio.samples.len(), "{format:?}");
+        if format.extension() == "wav" {
+            let reader = hound::WavReader::open(&path).unwrap();
+            assert_eq!(reader.len() as usize, audio.samples.len(), "{format:?}");
+        }
+    }
+}
+
+#[test]
+fn untagged_files_read_back_without_metadata() {
+    let tmp = tempdir().unwrap();
+    let audio = AudioData {
+        samples: mono_sine(44_100, 440.0, 0.1),
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+
+    for format in [OutputFormat::Wav16, OutputFormat::Flac16] {
+        let path = tmp.path().join(format!("plain.{}", format.extension()));
+        write_audio_with_format(path.to_str().unwrap(), &audio, format).unwrap();
+        assert!(read_audio(&path).unwrap().metadata.is_empty(), "{format:?}");
+    }
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Signal-to-error ratio in dB of `decoded` against `reference`, at the delay
+/// (up to `max_delay` frames) where they line up best. Lossy encoders prepend
+/// priming samples.
+fn aligned_snr_db(reference: &[f32], decoded: &[f32], max_delay: usize) -> f64 {
+    let frames = reference.len() / 2;
+    (0..=max_delay)
+        .filter(|delay| (delay + frames) * 2 <= decoded.len())
+        .map(|delay| {
+            let shifted = &decoded[delay * 2..(delay + frames) * 2];
+            let (signal, error) = reference
+                .iter()
+                .zip(shifted)
+                .fold((0.0, 0.0), |acc, (r, d)| {
+                    let (r, d) = (*r as f64, *d as f64);
+                    (acc.0 + r * r, acc.1 + (r - d) * (r - d))
+                });
+            10.0 * (signal / error).log10()
+        })
+        .fold(f64::NEG_INFINITY, f64::max)
+}
+
+#[test]
+fn lossless_fixtures_decode_to_the_same_samples() {
+    // 0.1 s of 440 Hz left / 660 Hz right at 16-bit 44.1 kHz; see
+    // tests/fixtures/README.md.
+    let reference = read_audio(fixture("tone.wav")).unwrap();
+    assert_eq!(reference.samples.len(), 4_410 * 2);
+
+    for name in ["tone.flac", "tone.aiff", "tone.caf", "tone_alac.m4a"] {
+        let decoded = read_audio(fixture(name)).unwrap_or_else(|e| panic!("{name}: {e:#}"));
+        assert_eq!(decoded.sample_rate, 44_100, "{name}");
+        assert_eq!(decoded.channels, 2, "{name}");
+        assert_eq!(decoded.samples, reference.samples, "{name}");
+        assert!(decoded.damage.is_clean(), "{name}: {:?}", decoded.damage);
+    }
+}
+
+#[test]
+fn lossy_fixtures_decode_close_to_the_source() {
+    let reference = read_audio(fixture("tone.wav")).unwrap().samples;
+
+    for name in ["tone.mp3", "tone.ogg", "tone_aac.m4a"] {
+        let decoded = read_audio(fixture(name)).unwrap_or_else(|e| panic!("{name}: {e:#}"));
+        assert_eq!(decoded.sample_rate, 44_100, "{name}");
+        assert_eq!(decoded.channels, 2, "{name}");
+        let snr = aligned_snr_db(&reference, &decoded.samples, 4_096);
+        assert!(snr > 20.0, "{name}: {snr:.1} dB");
+        assert!(decoded.damage.is_clean(), "{name}: {:?}", decoded.damage);
+    }
+}
+
+#[test]
+fn gapless_fixtures_line_up_with_the_source() {
+    // tone.mp3 has a LAME tag (576 + 529 frames of delay, 245 of padding) and
+    // tone_aac.m4a an iTunSMPB tag (2048 frames of priming, 4410 valid).
+    let reference = read_audio(fixture("tone.wav")).unwrap().samples;
+
+    for name in ["tone.mp3", "tone_aac.m4a"] {
+        let decoded = read_audio(fixture(name)).unwrap();
+        assert_eq!(decoded.samples.len(), reference.len(), "{name}");
+        let snr = aligned_snr_db(&reference, &decoded.samples, 0);
+        assert!(snr > 20.0, "{name}: {snr:.1} dB");
+        assert!(
+            decoded.metadata.tags.is_empty(),
+            "{name}: {:?}",
+            decoded.metadata
+        );
+
+        let mut reader = AudioReader::open(fixture(name)).unwrap();
+        assert_eq!(reader.n_frames(), Some(4_410), "{name}");
+        reader.seek(2_205).unwrap();
+        let mut tail = Vec::new();
+        while reader.read_frames(1_024, &mut tail).unwrap() > 0 {}
+        assert_eq!(tail.len(), reference.len() / 2, "{name}");
+        let snr = aligned_snr_db(&reference[2_205 * 2..], &tail, 0);
+        assert!(snr > 20.0, "{name} after seek: {snr:.1} dB");
+    }
+}
+
+#[test]
+fn corrupt_packets_are_skipped_and_counted() {
+    let tmp = tempdir().unwrap();
+    let clean = read_audio(fixture("tone_aac.m4a")).unwrap();
+    let path = tmp.path().join("corrupt.m4a");
+    let mut bytes = std::fs::read(fixture("tone_aac.m4a")).unwrap();
+    let mid = bytes.len() / 2;
+    for byte in &mut bytes[mid..mid + 64] {
+        *byte ^= 0xa5;
+    }
+    std::fs::write(&path, bytes).unwrap();
+
+    let decoded = read_audio(&path).unwrap();
+    assert!(decoded.damage.skipped_packets >= 1, "{:?}", decoded.damage);
+    assert_eq!(
+        decoded.samples.len() as u64 / 2 + decoded.damage.dropped_frames,
+        clean.samples.len() as u64 / 2
+    );
+}
+
+#[test]
+fn truncated_file_reads_to_its_end_without_damage() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("truncated.wav");
+    let bytes = std::fs::read(fixture("tone.wav")).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() * 2 / 3]).unwrap();
+
+    let decoded = read_audio(&path).unwrap();
+    let frames = decoded.samples.len() / 2;
+    assert!(frames > 2_000 && frames < 4_410, "{frames}");
+    assert!(decoded.damage.is_clean());
+}
+
+#[test]
+fn unsupported_input_reports_the_format() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("notes.txt");
+    std::fs::write(&path, "not audio at all").unwrap();
+
+    let err = read_audio(&path).unwrap_err();
+    assert!(
+        err.to_string().starts_with("Unsupported audio format"),
+        "{err:#}"
+    );
+}
+
+#[test]
+fn list_tracks_reports_each_audio_track() {
+    // A video track, then the tone in English and its left channel at
+    // 22.05 kHz in Japanese; see tests/fixtures/README.md.
+    let tracks = list_tracks(fixture("tracks.mkv")).unwrap();
+    assert_eq!(
+        tracks,
+        vec![
+            AudioTrack {
+                index: 0,
+                id: 2,
+                codec: Some("flac".into()),
+                channels: Some(2),
+                sample_rate: Some(44_100),
+                language: Some("eng".into()),
+                default: true,
+            },
+            AudioTrack {
+                index: 1,
+                id: 3,
+                codec: Some("flac".into()),
+                channels: Some(1),
+                sample_rate: Some(22_050),
+                language: Some("jpn".into()),
+                default: false,
+            },
+        ]
+    );
+
+    let tracks = list_tracks(fixture("tone_aac.m4a")).unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].codec.as_deref(), Some("aac"));
+    assert_eq!(tracks[0].channels, Some(2));
+    assert!(tracks[0].default);
+}
+
+#[test]
+fn tracks_are_selected_by_index_or_language() {
+    let reference = read_audio(fixture("tone.wav")).unwrap();
+    let commentary: Vec<f32> = reference.samples.iter().step_by(4).copied().collect();
+
+    // The default skips the video track.
+    assert_eq!(
+        read_audio(fixture("tracks.mkv")).unwrap().samples,
+        reference.samples
+    );
+
+    for selector in ["1", "JPN"] {
+        let track: TrackSelector = selector.parse().unwrap();
+        let audio = read_audio_track(fixture("tracks.mkv"), &track).unwrap();
+        assert_eq!(audio.sample_rate, 22_050, "{selector}");
+        assert_eq!(audio.channels, 1, "{selector}");
+        assert_eq!(audio.samples, commentary, "{selector}");
+    }
+
+    let err = read_audio_track(fixture("tracks.mkv"), &TrackSelector::Index(2)).unwrap_err();
+    assert!(err.to_string().contains("2 audio tracks"), "{err:#}");
+    let err = read_audio_track(fixture("tracks.mkv"), &"fre".parse().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("eng, jpn"), "{err:#}");
+}
@@ -0,0 +1,205 @@
+#![cfg(feature = "engine-mock")]
+
+// Lives in its own test binary because it interrupts splits from the split
+// progress callback, which can only be set once per process.
+
+mod common;
+
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tempfile::tempdir;
+
+use stem_splitter_core::core::audio::{read_audio, write_audio};
+use stem_splitter_core::core::splitter::split_file;
+use stem_splitter_core::{
+    set_split_progress_callback, AudioData, CancelToken, MultichannelMode, SplitOptions,
+    SplitProgress, StemError,
+};
+
+/// Chunk counts reported by the current split, and the chunk after which it
+/// gets cancelled.
+struct Interrupt {
+    cancel_after: Option<(usize, CancelToken)>,
+    chunks_seen: Vec<usize>,
+}
+
+fn interrupt() -> &'static Mutex<Interrupt> {
+    static INTERRUPT: OnceLock<Mutex<Interrupt>> = OnceLock::new();
+    INTERRUPT.get_or_init(|| {
+        set_split_progress_callback(|progress| {
+            if let SplitProgress::Chunks { done, .. } = progress {
+                let mut state = interrupt().lock().unwrap();
+                state.chunks_seen.push(done);
+                if let Some((after, cancel)) = &state.cancel_after {
+                    if done == *after {
+                        cancel.cancel();
+                    }
+                }
+            }
+        });
+        Mutex::new(Interrupt {
+            cancel_after: None,
+            chunks_seen: Vec::new(),
+        })
+    })
+}
+
+/// Serializes the tests, which share the progress callback.
+fn test_lock() -> MutexGuard<'static, ()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+fn options(out_dir: &Path) -> SplitOptions {
+    SplitOptions {
+        resample_to_source_rate: true,
+        checkpoint: true,
+        ..common::mock_options(out_dir)
+    }
+}
+
+/// Run a checkpointed split, cancelling it once `cancel_after` chunks are done,
+/// and return the chunk counts it reported.
+fn split(
+    input: &Path,
+    out_dir: &Path,
+    cancel_after: Option<usize>,
+) -> (Result<(), StemError>, Vec<usize>) {
+    split_with(input, options(out_dir), cancel_after)
+}
+
+fn split_with(
+    input: &Path,
+    opts: SplitOptions,
+    cancel_after: Option<usize>,
+) -> (Result<(), StemError>, Vec<usize>) {
+    let cancel = CancelToken::new();
+    {
+        let mut state = interrupt().lock().unwrap();
+        state.cancel_after = cancel_after.map(|after| (after, cancel.clone()));
+        state.chunks_seen.clear();
+    }
+    let opts = SplitOptions { cancel, ..opts };
+    let result = split_file(input.to_str().unwrap(), opts).map(|_| ());
+    let seen = std::mem::take(&mut interrupt().lock().unwrap().chunks_seen);
+    (result, seen)
+}
+
+fn write_stereo_sine(path: &Path, sr: u32, frames: usize, freq: f32) {
+    let mut samples = Vec::with_capacity(frames * 2);
+    for i in 0..frames {
+        let t = i as f32 / sr as f32;
+        samples.push((2.0 * PI * freq * t).sin() * 0.2);
+        samples.push((2.0 * PI * freq * 1.5 * t).sin() * 0.2);
+    }
+    let audio = AudioData {
+        samples,
+        sample_rate: sr,
+        channels: 2,
+        ..Default::default()
+    };
+    write_audio(path.to_str().unwrap(), &audio).unwrap();
+}
+
+fn stem_samples(out_dir: &Path, name: &str) -> Vec<Vec<f32>> {
+    ["vocals", "drums", "bass", "other"]
+        .iter()
+        .map(|stem| {
+            let path = out_dir.join(format!("{name}_{stem}.wav"));
+            read_audio(path.to_str().unwrap()).unwrap().samples
+        })
+        .collect()
+}
+
+fn checkpoint_dir(out_dir: &Path, name: &str) -> PathBuf {
+    out_dir.join(format!("{name}.checkpoint"))
+}
+
+#[test]
+fn interrupted_split_resumes_from_its_checkpoint() {
+    let _lock = test_lock();
+    let tmp = tempdir().unwrap();
+    let input = tmp.path().join("song.wav");
+    // 48 kHz input written back at 48 kHz, so both resamplers and the 16-bit
+    // dither have to pick up exactly where the interrupted run stopped.
+    write_stereo_sine(&input, 48_000, 40_000, 440.0);
+
+    let reference_dir = tmp.path().join("reference");
+    let (result, _) = split(&input, &reference_dir, None);
+    result.unwrap();
+    assert!(!checkpoint_dir(&reference_dir, "song").exists());
+
+    let out_dir = tmp.path().join("out");
+    let (result, _) = split(&input, &out_dir, Some(5));
+    assert!(matches!(result, Err(StemError::Cancelled)), "{result:?}");
+    assert!(checkpoint_dir(&out_dir, "song").join("state.bin").exists());
+    assert!(!out_dir.join("song_vocals.wav").exists());
+
+    let (result, seen) = split(&input, &out_dir, None);
+    result.unwrap();
+    assert_eq!(seen.first(), Some(&6), "resumed after chunk 5: {seen:?}");
+    assert!(!checkpoint_dir(&out_dir, "song").exists());
+    assert_eq!(
+        stem_samples(&out_dir, "song"),
+        stem_samples(&reference_dir, "song")
+    );
+}
+
+#[test]
+fn checkpoint_of_a_changed_input_is_discarded() {
+    let _lock = test_lock();
+    let tmp = tempdir().unwrap();
+    let input = tmp.path().join("take.wav");
+    write_stereo_sine(&input, 44_100, 30_000, 440.0);
+
+    let out_dir = tmp.path().join("out");
+    let (result, _) = split(&input, &out_dir, Some(3));
+    assert!(matches!(result, Err(StemError::Cancelled)), "{result:?}");
+    assert!(checkpoint_dir(&out_dir, "take").exists());
+
+    write_stereo_sine(&input, 44_100, 30_000, 330.0);
+    let (result, seen) = split(&input, &out_dir, None);
+    result.unwrap();
+    assert_eq!(seen.first(), Some(&1), "started over: {seen:?}");
+
+    let reference_dir = tmp.path().join("reference");
+    let (result, _) = split(&input, &reference_dir, None);
+    result.unwrap();
+    assert_eq!(
+        stem_samples(&out_dir, "take"),
+        stem_samples(&reference_dir, "take")
+    );
+}
+
+#[test]
+fn per_pair_split_of_surround_input_resumes() {
+    let _lock = test_lock();
+    let tmp = tempdir().unwrap();
+    let input = tmp.path().join("film.wav");
+    let audio = common::surround_audio(30_000);
+    write_audio(input.to_str().unwrap(), &audio).unwrap();
+    let opts = |out_dir: &Path| SplitOptions {
+        multichannel: MultichannelMode::PerPair,
+        ..options(out_dir)
+    };
+
+    let reference_dir = tmp.path().join("reference");
+    split_with(&input, opts(&reference_dir), None).0.unwrap();
+
+    let out_dir = tmp.path().join("out");
+    let (result, _) = split_with(&input, opts(&out_dir), Some(4));
+    assert!(matches!(result, Err(StemError::Cancelled)), "{result:?}");
+    let (result, seen) = split_with(&input, opts(&out_dir), None);
+    result.unwrap();
+    assert_eq!(seen.first(), Some(&5), "resumed after chunk 4: {seen:?}");
+
+    let vocals = read_audio(out_dir.join("film_vocals.wav")).unwrap();
+    assert_eq!(vocals.channels, 6);
+    assert_eq!(
+        stem_samples(&out_dir, "film"),
+        stem_samples(&reference_dir, "film")
+    );
+}
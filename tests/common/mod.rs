@@ -0,0 +1,147 @@
+// Mock model scaffolding shared by the engine-mock integration tests. Each
+// test binary compiles its own copy, so not every helper is used everywhere.
+#![allow(dead_code)]
+
+use httpmock::prelude::*;
+use sha2::{Digest, Sha256};
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::OnceLock;
+use tempfile::tempdir;
+
+use stem_splitter_core::core::audio::write_audio;
+use stem_splitter_core::{AudioData, SplitOptions, SplitResult};
+
+// Compute hex sha256 for arbitrary bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    let mut h = Sha256::new();
+    h.update(bytes);
+    hex::encode(h.finalize())
+}
+
+pub fn manifest_json(model_url: &str, sha_hex: &str, stems: &[&str]) -> String {
+    format!(
+        r#"{{
+  "name": "mdx_mock",
+  "version": "1.0.0",
+  "backend": "onnx",
+  "sample_rate": 44100,
+  "window": 4096,
+  "hop": 2048,
+  "stems": {stems},
+  "input_layout": "BCT",
+  "output_layout": "BSCT",
+  "artifacts": [
+    {{
+      "file": "mock.onnx",
+      "url": "{url}",
+      "sha256": "{sha}",
+      "size_bytes": 0
+    }}
+  ]
+}}"#,
+        stems = serde_json::to_string(stems).unwrap(),
+        url = model_url,
+        sha = sha_hex
+    )
+}
+
+pub struct MockModel {
+    _server: MockServer,
+    pub manifest_url: String,
+}
+
+impl MockModel {
+    /// Serve a mock model separating into `stems`, with the model cache in a
+    /// fresh temp dir.
+    pub fn serve(stems: &[&str]) -> MockModel {
+        let cache = tempdir().unwrap().keep();
+        std::env::set_var("XDG_CACHE_HOME", &cache);
+
+        let server = MockServer::start();
+        let model_body = b"this is the mock onnx payload";
+        let model_sha = sha256_hex(model_body);
+
+        server.mock(|when, then| {
+            when.method(GET).path("/mock.onnx");
+            then.status(200)
+                .header("Content-Length", model_body.len().to_string().as_str())
+                .body(model_body.as_slice());
+        });
+
+        server.mock(|when, then| {
+            when.method(GET).path("/m.json");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(manifest_json(
+                    &format!("{}/mock.onnx", server.base_url()),
+                    &model_sha,
+                    stems,
+                ));
+        });
+
+        let manifest_url = format!("{}/m.json", server.base_url());
+        MockModel {
+            _server: server,
+            manifest_url,
+        }
+    }
+
+    pub fn options(&self, out_dir: &Path) -> SplitOptions {
+        SplitOptions {
+            model_name: "ignored".into(),
+            manifest_url_override: Some(self.manifest_url.clone()),
+            output_dir: out_dir.to_string_lossy().into(),
+            ..Default::default()
+        }
+    }
+}
+
+// All tests of a binary share one cache dir: the engine mock keeps the first
+// manifest it sees for the whole process, and XDG_CACHE_HOME is process-global
+// anyway.
+pub fn mock_model() -> &'static MockModel {
+    static MODEL: OnceLock<MockModel> = OnceLock::new();
+    MODEL.get_or_init(|| MockModel::serve(&["vocals", "drums", "bass", "other"]))
+}
+
+pub fn mock_options(out_dir: &Path) -> SplitOptions {
+    mock_model().options(out_dir)
+}
+
+pub fn write_stereo_sine(path: &Path, sr: u32, frames: usize) {
+    let mut samples = Vec::with_capacity(frames * 2);
+    for i in 0..frames {
+        let t = i as f32 / sr as f32;
+        samples.push((2.0 * PI * 440.0 * t).sin() * 0.2);
+        samples.push((2.0 * PI * 660.0 * t).sin() * 0.2);
+    }
+    let audio = AudioData {
+        samples,
+        sample_rate: sr,
+        channels: 2,
+        ..Default::default()
+    };
+    write_audio(path.to_str().unwrap(), &audio).unwrap();
+}
+
+/// 5.1 input (`L R C LFE Ls Rs`) with a different sine on every channel.
+pub fn surround_audio(frames: usize) -> AudioData {
+    let samples = (0..frames)
+        .flat_map(|i| {
+            (0..6).map(move |ch| {
+                (2.0 * PI * (220.0 + 110.0 * ch as f32) * i as f32 / 44_100.0).sin() * 0.1
+            })
+        })
+        .collect();
+    AudioData {
+        samples,
+        sample_rate: 44_100,
+        channels: 6,
+        ..Default::default()
+    }
+}
+
+pub fn stem_paths(res: &SplitResult) -> Vec<&String> {
+    res.stems.values().map(|stem| &stem.path).collect()
+}
@@ -1,5 +1,9 @@
 use approx::assert_abs_diff_eq;
-use stem_splitter_core::core::dsp::{istft_cac_stereo, stft_cac_stereo_centered, to_planar_stereo};
+use stem_splitter_core::core::dsp::{
+    istft_cac_stereo, mix_consistency, stft_cac_stereo_centered, to_planar_stereo,
+    transition_weights, wiener_filter,
+};
+use stem_splitter_core::{MixConsistency, OverlapWindow};
 
 #[test]
 fn to_planar_stereo_mono_duplicates_channel() {
@@ -63,3 +67,138 @@ fn stft_demucs_dims_reference() {
     assert_eq!(f_bins, 2048);
     assert_eq!(frames, 1 + (t / hop));
 }
+
+#[test]
+fn triangular_weights_peak_in_the_middle() {
+    let w = transition_weights(OverlapWindow::Triangular, 8, 4);
+    assert_eq!(w.len(), 8);
+    assert!(w.iter().all(|&x| x > 0.0 && x <= 1.0));
+    assert_abs_diff_eq!(w[0], 0.25, epsilon = 1e-7);
+    assert_abs_diff_eq!(w[3], 1.0, epsilon = 1e-7);
+    assert_abs_diff_eq!(w[4], 1.0, epsilon = 1e-7);
+    assert_abs_diff_eq!(w[7], 0.25, epsilon = 1e-7);
+}
+
+#[test]
+fn linear_weights_only_fade_across_the_overlap() {
+    let w = transition_weights(OverlapWindow::Linear, 16, 3);
+    assert_abs_diff_eq!(w[0], 0.25, epsilon = 1e-7);
+    assert_abs_diff_eq!(w[2], 0.75, epsilon = 1e-7);
+    assert!(w[3..13].iter().all(|&x| x == 1.0));
+    assert_abs_diff_eq!(w[15], 0.25, epsilon = 1e-7);
+
+    let flat = transition_weights(OverlapWindow::Linear, 16, 0);
+    assert!(flat.iter().all(|&x| x == 1.0));
+}
+
+/// Two stereo tones panned to opposite sides, planar as `[source][channel][sample]`.
+fn panned_tones(t: usize) -> Vec<f32> {
+    let tone =
+        |freq: f32, i: usize| (2.0 * std::f32::consts::PI * freq * i as f32 / 44_100.0).sin();
+    let mut sources = Vec::with_capacity(4 * t);
+    sources.extend((0..t).map(|i| 0.5 * tone(440.0, i)));
+    sources.extend((0..t).map(|i| 0.2 * tone(440.0, i)));
+    sources.extend((0..t).map(|i| 0.1 * tone(3000.0, i)));
+    sources.extend((0..t).map(|i| 0.4 * tone(3000.0, i)));
+    sources
+}
+
+/// Squared error of planar `[source][channel][sample]` estimates, leaving out
+/// `margin` samples at either end of every channel.
+fn error_energy(estimate: &[f32], truth: &[f32], t: usize, margin: usize) -> f32 {
+    estimate
+        .chunks(t)
+        .zip(truth.chunks(t))
+        .flat_map(|(e, x)| e[margin..t - margin].iter().zip(&x[margin..t - margin]))
+        .map(|(e, x)| (e - x) * (e - x))
+        .sum()
+}
+
+#[test]
+fn wiener_filter_reduces_bleed_and_keeps_the_mixture() {
+    let t = 16_384usize;
+    let truth = panned_tones(t);
+    let (a, b) = truth.split_at(2 * t);
+    let mix_left: Vec<f32> = (0..t).map(|i| a[i] + b[i]).collect();
+    let mix_right: Vec<f32> = (0..t).map(|i| a[t + i] + b[t + i]).collect();
+
+    // Each estimate leaks 30% of the other source.
+    let estimates: Vec<f32> = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| x + 0.3 * y)
+        .chain(a.iter().zip(b).map(|(x, y)| y + 0.3 * x))
+        .collect();
+    let before = error_energy(&estimates, &truth, t, 2048);
+
+    // EM iterations sharpen the models until almost no bleed is left.
+    let mut errors = vec![before];
+    let mut refined = estimates.clone();
+    for iterations in 1..=3 {
+        refined.copy_from_slice(&estimates);
+        wiener_filter(&mix_left, &mix_right, &mut refined, iterations, 4096, 1024);
+        errors.push(error_energy(&refined, &truth, t, 2048));
+    }
+    assert!(errors[1] < before / 5.0, "{errors:?}");
+    assert!(
+        errors[2..].iter().all(|&e| e < before / 1000.0),
+        "{errors:?}"
+    );
+    let estimates = refined;
+
+    // Away from the edges, the refined sources sum back to the mixture.
+    for i in 2048..t - 2048 {
+        assert_abs_diff_eq!(
+            estimates[i] + estimates[2 * t + i],
+            mix_left[i],
+            epsilon = 1e-3
+        );
+        assert_abs_diff_eq!(
+            estimates[t + i] + estimates[3 * t + i],
+            mix_right[i],
+            epsilon = 1e-3
+        );
+    }
+}
+
+#[test]
+fn wiener_filter_without_iterations_is_a_no_op() {
+    let t = 4096usize;
+    let mut sources = panned_tones(t);
+    let original = sources.clone();
+    let mix = vec![0.0f32; t];
+    wiener_filter(&mix, &mix, &mut sources, 0, 4096, 1024);
+    assert_eq!(sources, original);
+}
+
+#[test]
+fn mix_consistency_makes_sources_sum_to_the_mixture() {
+    let t = 4096usize;
+    let tone =
+        |freq: f32, i: usize| (2.0 * std::f32::consts::PI * freq * i as f32 / 44_100.0).sin();
+    // A loud and a quiet source, and a mixture with something neither holds.
+    let loud: Vec<f32> = (0..2 * t).map(|i| 0.5 * tone(220.0, i % t)).collect();
+    let quiet: Vec<f32> = (0..2 * t).map(|i| 0.05 * tone(1000.0, i % t)).collect();
+    let mix: Vec<f32> = (0..t)
+        .map(|i| loud[i] + quiet[i] + 0.1 * tone(5000.0, i))
+        .collect();
+
+    for method in [MixConsistency::Equal, MixConsistency::Energy] {
+        let mut sources = [loud.as_slice(), quiet.as_slice()].concat();
+        mix_consistency(&mix, &mix, &mut sources, method);
+        for ch in 0..2 {
+            for i in 0..t {
+                let sum = sources[ch * t + i] + sources[(2 + ch) * t + i];
+                assert_abs_diff_eq!(sum, mix[i], epsilon = 1e-6);
+            }
+        }
+
+        let added: f32 = (0..t).map(|i| (sources[2 * t + i] - quiet[i]).abs()).sum();
+        let residual: f32 = (0..t).map(|i| (0.1 * tone(5000.0, i)).abs()).sum();
+        match method {
+            MixConsistency::Equal => assert_abs_diff_eq!(added, residual / 2.0, epsilon = 1e-2),
+            // 0.05 against 0.5 in amplitude is a 1% share of the energy.
+            _ => assert!(added < residual * 0.011, "{added} of {residual}"),
+        }
+    }
+}
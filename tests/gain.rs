@@ -0,0 +1,133 @@
+use approx::assert_abs_diff_eq;
+use std::f32::consts::PI;
+use stem_splitter_core::core::gain::{db_to_gain, stem_gains, Limiter};
+use stem_splitter_core::{GainStaging, StemStats};
+
+fn levels(true_peak: f32, integrated_lufs: f32) -> StemStats {
+    StemStats {
+        sample_peak: true_peak,
+        true_peak,
+        integrated_lufs,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn common_gain_lowers_every_stem_by_the_loudest_peak() {
+    let stems = [
+        levels(2.0, -10.0),
+        levels(0.5, -20.0),
+        levels(0.0, f32::NEG_INFINITY),
+    ];
+    let gains = stem_gains(GainStaging::Common { ceiling_db: 0.0 }, &stems);
+    assert_eq!(gains, vec![0.5; 3]);
+
+    // Quiet stems are not boosted.
+    let quiet = [levels(0.25, -30.0)];
+    assert_eq!(
+        stem_gains(GainStaging::Common { ceiling_db: 0.0 }, &quiet),
+        [1.0]
+    );
+}
+
+#[test]
+fn normalization_scales_each_stem_to_its_target() {
+    let stems = [
+        levels(2.0, -10.0),
+        levels(0.25, -30.0),
+        levels(0.0, f32::NEG_INFINITY),
+    ];
+
+    let gains = stem_gains(GainStaging::NormalizePeak { peak_db: -6.0 }, &stems);
+    assert_abs_diff_eq!(gains[0] * 2.0, db_to_gain(-6.0), epsilon = 1e-6);
+    assert_abs_diff_eq!(gains[1] * 0.25, db_to_gain(-6.0), epsilon = 1e-6);
+    assert_eq!(gains[2], 1.0);
+
+    let stems = [
+        levels(2.0, -10.0),
+        levels(0.5, -40.0),
+        levels(0.0, f32::NEG_INFINITY),
+    ];
+    let gains = stem_gains(GainStaging::NormalizeLoudness { lufs: -25.0 }, &stems);
+    assert_abs_diff_eq!(gains[0], db_to_gain(-15.0), epsilon = 1e-6);
+    // +15 dB would push the 0.5 peak past full scale, so the gain stops at 0 dBTP.
+    assert_abs_diff_eq!(gains[1], 2.0, epsilon = 1e-6);
+    assert_eq!(gains[2], 1.0);
+}
+
+#[test]
+fn limiter_keeps_peaks_under_the_ceiling_without_changing_length() {
+    let sample_rate = 44_100;
+    // A quiet tone with a 4x louder burst in the middle.
+    let input: Vec<f32> = (0..2 * sample_rate)
+        .flat_map(|i| {
+            let level = if (20_000..22_000).contains(&i) {
+                2.0
+            } else {
+                0.5
+            };
+            let x = (2.0 * PI * 220.0 * i as f32 / sample_rate as f32).sin() * level;
+            [x, -x]
+        })
+        .collect();
+
+    let mut limiter = Limiter::new(sample_rate as u32, -1.0);
+    let mut output = Vec::new();
+    for block in input.chunks(1000) {
+        limiter.process_interleaved(block, &mut output);
+    }
+    limiter.flush(&mut output);
+
+    assert_eq!(output.len(), input.len());
+    let ceiling = db_to_gain(-1.0);
+    assert!(output.iter().all(|x| x.abs() <= ceiling));
+    let burst_peak = output[40_000..44_000]
+        .iter()
+        .fold(0f32, |m, x| m.max(x.abs()));
+    assert!(burst_peak > 0.99 * ceiling, "burst peak {burst_peak}");
+
+    // Well before the burst and long after it, the tone passes untouched.
+    for i in (0..30_000).chain(132_000..input.len()) {
+        assert_abs_diff_eq!(output[i], input[i], epsilon = 1e-4);
+    }
+}
+
+#[test]
+fn limiter_links_every_channel_of_a_frame() {
+    let sample_rate = 44_100;
+    // Six channels; only the centre gets loud, but all of them are turned down.
+    let input: Vec<f32> = (0..sample_rate)
+        .flat_map(|i| {
+            let x = (2.0 * PI * 220.0 * i as f32 / sample_rate as f32).sin() * 0.5;
+            let center = if (20_000..22_000).contains(&i) {
+                4.0 * x
+            } else {
+                x
+            };
+            [x, x, center, 0.0, x, x]
+        })
+        .collect();
+
+    let mut limiter = Limiter::with_channels(sample_rate as u32, 6, -1.0);
+    let mut output = Vec::new();
+    for block in input.chunks(996) {
+        limiter.process_interleaved(block, &mut output);
+    }
+    limiter.flush(&mut output);
+
+    assert_eq!(output.len(), input.len());
+    let ceiling = db_to_gain(-1.0);
+    assert!(output.iter().all(|x| x.abs() <= ceiling));
+    for (out, frame) in output
+        .chunks_exact(6)
+        .zip(input.chunks_exact(6))
+        .skip(20_000)
+        .take(2000)
+    {
+        if frame[0].abs() > 0.1 {
+            let gain = out[0] / frame[0];
+            assert_abs_diff_eq!(out[2], frame[2] * gain, epsilon = 1e-5);
+            assert!(gain < 0.5, "front channels limited with the centre: {gain}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use approx::assert_abs_diff_eq;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+use stem_splitter_core::core::meter::Meter;
+
+fn stereo_sine(sample_rate: u32, freq: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+    let n = (sample_rate as f32 * seconds) as usize;
+    (0..n)
+        .flat_map(|i| {
+            let x = (2.0 * PI * freq * i as f32 / sample_rate as f32).sin() * amplitude;
+            [x, x]
+        })
+        .collect()
+}
+
+#[test]
+fn ebu_reference_tone_measures_minus_23_lufs() {
+    // EBU Tech 3341 case 1: a 1 kHz stereo sine at -23 dBFS reads -23 LUFS.
+    let amplitude = 10f32.powf(-23.0 / 20.0);
+    for sample_rate in [44_100, 48_000] {
+        let mut meter = Meter::new(sample_rate, 2);
+        // Fed in odd block sizes to cross sub-block boundaries.
+        for block in stereo_sine(sample_rate, 1000.0, amplitude, 20.0).chunks(2 * 1237) {
+            meter.process_interleaved(block);
+        }
+        let stats = meter.finish();
+
+        assert_abs_diff_eq!(stats.integrated_lufs, -23.0, epsilon = 0.1);
+        assert_abs_diff_eq!(stats.sample_peak, amplitude, epsilon = 1e-4);
+        assert_abs_diff_eq!(stats.rms, amplitude / 2f32.sqrt(), epsilon = 1e-4);
+    }
+}
+
+#[test]
+fn silence_is_gated_out_of_integrated_loudness() {
+    let amplitude = 10f32.powf(-23.0 / 20.0);
+    let mut samples = stereo_sine(48_000, 1000.0, amplitude, 10.0);
+    samples.extend(vec![0.0; 48_000 * 2 * 10]);
+
+    let mut meter = Meter::new(48_000, 2);
+    meter.process_interleaved(&samples);
+    assert_abs_diff_eq!(meter.finish().integrated_lufs, -23.0, epsilon = 0.1);
+
+    let mut meter = Meter::new(48_000, 2);
+    meter.process_interleaved(&vec![0.0; 48_000 * 2]);
+    let stats = meter.finish();
+    assert_eq!(stats.integrated_lufs, f32::NEG_INFINITY);
+    assert_eq!(
+        (stats.sample_peak, stats.true_peak, stats.rms),
+        (0.0, 0.0, 0.0)
+    );
+}
+
+#[test]
+fn true_peak_finds_peaks_between_samples() {
+    // A quarter-rate sine sampled 45 degrees off its peaks never has a sample
+    // above 0.707, though the waveform reaches 1.0.
+    let samples: Vec<f32> = (0..4_000)
+        .flat_map(|i| {
+            let x = (PI / 2.0 * i as f32 + PI / 4.0).sin();
+            [x, x]
+        })
+        .collect();
+    let mut meter = Meter::new(48_000, 2);
+    meter.process_interleaved(&samples);
+    let stats = meter.finish();
+
+    assert_abs_diff_eq!(stats.sample_peak, FRAC_1_SQRT_2, epsilon = 1e-3);
+    assert!(stats.true_peak > 0.97, "true peak {}", stats.true_peak);
+}
+
+#[test]
+fn surround_channels_are_weighted_and_lfe_is_left_out() {
+    // 5.1 (`L R C LFE Ls Rs`) with a 1 kHz sine at -23 dBFS on every channel.
+    // Each one adds half the energy of the -23 LUFS stereo reference; per
+    // BS.1770 the surrounds count 1.41 times and the LFE not at all, so the
+    // mix reads -23 + 10 * log10((3 + 2 * 1.41) / 2) LUFS.
+    let amplitude = 10f32.powf(-23.0 / 20.0);
+    let samples: Vec<f32> = stereo_sine(48_000, 1000.0, amplitude, 10.0)
+        .iter()
+        .step_by(2)
+        .flat_map(|&x| [x; 6])
+        .collect();
+    let expected = -23.0 + 10.0 * ((3.0 + 2.0 * 1.41f32) / 2.0).log10();
+
+    let mut meter = Meter::with_channel_weights(48_000, &[1.0, 1.0, 1.0, 0.0, 1.41, 1.41]);
+    meter.process_interleaved(&samples);
+    assert_abs_diff_eq!(meter.finish().integrated_lufs, expected, epsilon = 0.1);
+}
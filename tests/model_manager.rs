@@ -5,7 +5,8 @@ use tempfile::tempdir;
 
 use httpmock::prelude::*;
 
-use stem_splitter_core::model::model_manager::ensure_model;
+use stem_splitter_core::model::model_manager::{ensure_model, ensure_model_cancellable};
+use stem_splitter_core::{set_download_progress_callback, CancelToken, StemError};
 
 fn env_lock() -> &'static Mutex<()> {
     static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
@@ -191,3 +192,61 @@ fn checksum_mismatch_returns_error() {
         }
     }
 }
+
+#[test]
+fn cancelled_download_returns_cancelled_and_leaves_no_files() {
+    let _lock = env_lock().lock().unwrap();
+    let tmp_cache = tempdir().unwrap();
+    let _cache_home = CacheHomeGuard::set(tmp_cache.path());
+
+    let (model_bytes, sha_hex, size) = make_fake_model_bytes(1024 * 1024);
+    let server = MockServer::start();
+    let model_url = format!("{}/slow.onnx", server.base_url());
+
+    let model_mock = server.mock(|when, then| {
+        when.method(GET).path("/slow.onnx");
+        then.status(200)
+            .header("Content-Length", size.to_string().as_str())
+            .body(model_bytes.clone());
+    });
+    let manifest_body = manifest_json("cancel_me", "slow.onnx", &model_url, &sha_hex, size);
+    server.mock(|when, then| {
+        when.method(GET).path("/cancel_me.json");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .body(manifest_body.clone());
+    });
+    let manifest_url = format!("{}/cancel_me.json", server.base_url());
+
+    // Cancel as soon as the first block of the model has been written.
+    static CANCEL: OnceLock<CancelToken> = OnceLock::new();
+    let cancel = CANCEL.get_or_init(CancelToken::new);
+    set_download_progress_callback(|done, _| {
+        if done > 0 {
+            CANCEL.get().unwrap().cancel();
+        }
+    });
+
+    let err = ensure_model_cancellable("ignored", Some(&manifest_url), cancel)
+        .err()
+        .expect("expected the download to be cancelled");
+    assert!(matches!(err, StemError::Cancelled), "got {err}");
+    model_mock.assert_hits(1);
+
+    let leftovers: Vec<_> = std::fs::read_dir(tmp_cache.path())
+        .unwrap()
+        .flat_map(|entry| walk(&entry.unwrap().path()))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind: {leftovers:?}");
+}
+
+fn walk(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+    if path.is_dir() {
+        std::fs::read_dir(path)
+            .unwrap()
+            .flat_map(|entry| walk(&entry.unwrap().path()))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
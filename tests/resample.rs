@@ -0,0 +1,117 @@
+use std::f32::consts::PI;
+
+use stem_splitter_core::core::resample::{resample_audio, resample_interleaved, StreamResampler};
+use stem_splitter_core::AudioData;
+
+fn stereo_sine(sample_rate: u32, freq: f32, frames: usize) -> Vec<f32> {
+    let mut out = Vec::with_capacity(frames * 2);
+    for i in 0..frames {
+        let t = i as f32 / sample_rate as f32;
+        let s = (2.0 * PI * freq * t).sin() * 0.5;
+        out.push(s);
+        out.push(-s);
+    }
+    out
+}
+
+fn rising_zero_crossings(samples: &[f32], channels: usize) -> usize {
+    samples
+        .chunks_exact(channels)
+        .map(|f| f[0])
+        .collect::<Vec<_>>()
+        .windows(2)
+        .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+        .count()
+}
+
+#[test]
+fn resample_preserves_duration_and_pitch() {
+    let frames = 48_000;
+    let input = stereo_sine(48_000, 440.0, frames);
+
+    let out = resample_interleaved(&input, 2, 48_000, 44_100).unwrap();
+
+    assert_eq!(out.len(), 44_100 * 2);
+    let crossings = rising_zero_crossings(&out, 2);
+    assert!(
+        (438..=441).contains(&crossings),
+        "expected ~440 cycles, got {crossings}"
+    );
+}
+
+#[test]
+fn resample_keeps_signal_time_aligned() {
+    let from = 48_000u32;
+    let to = 44_100u32;
+    let frames = 16_384usize;
+    let impulse_at = 9_600usize;
+    let mut input = vec![0.0f32; frames];
+    input[impulse_at] = 1.0;
+
+    let out = resample_interleaved(&input, 1, from, to).unwrap();
+    let peak = out
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+        .map(|(i, _)| i)
+        .unwrap();
+
+    let expected = impulse_at as f64 * to as f64 / from as f64;
+    assert!(
+        (peak as f64 - expected).abs() <= 1.0,
+        "impulse moved from {expected} to {peak}"
+    );
+}
+
+#[test]
+fn streaming_matches_one_shot() {
+    let input = stereo_sine(44_100, 1_000.0, 10_000);
+    let one_shot = resample_interleaved(&input, 2, 44_100, 48_000).unwrap();
+
+    let mut resampler = StreamResampler::new(44_100, 48_000, 2).unwrap();
+    let mut streamed = Vec::new();
+    for block in input.chunks(2 * 777) {
+        resampler.process_interleaved(block, &mut streamed).unwrap();
+    }
+    let total = resampler.expected_output_frames();
+    resampler.flush(total, &mut streamed).unwrap();
+
+    assert_eq!(streamed.len(), one_shot.len());
+    for (a, b) in streamed.iter().zip(one_shot.iter()) {
+        assert!((a - b).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn flush_pads_to_requested_length() {
+    let input = stereo_sine(44_100, 220.0, 4_410);
+
+    let mut resampler = StreamResampler::new(44_100, 96_000, 2).unwrap();
+    let mut out = Vec::new();
+    resampler.process_interleaved(&input, &mut out).unwrap();
+    resampler.flush(9_601, &mut out).unwrap();
+    assert_eq!(out.len(), 9_601 * 2);
+}
+
+#[test]
+fn resample_audio_same_rate_is_identity() {
+    let audio = AudioData {
+        samples: stereo_sine(44_100, 440.0, 512),
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+
+    let out = resample_audio(&audio, 44_100).unwrap();
+    assert_eq!(out.sample_rate, 44_100);
+    assert_eq!(out.samples, audio.samples);
+}
+
+#[test]
+fn stream_resampler_rejects_partial_frames() {
+    let mut resampler = StreamResampler::new(48_000, 44_100, 2).unwrap();
+    let mut out = Vec::new();
+    assert!(resampler
+        .process_interleaved(&[0.0, 0.1, 0.2], &mut out)
+        .is_err());
+}
@@ -1,114 +1,1072 @@
 #![cfg(feature = "engine-mock")]
 
-use httpmock::prelude::*;
-use sha2::{Digest, Sha256};
+mod common;
+
+use common::{mock_options, stem_paths, surround_audio, write_stereo_sine};
+
 use std::f32::consts::PI;
 use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
 use tempfile::tempdir;
 
 use hound;
-use stem_splitter_core::core::audio::write_audio;
-use stem_splitter_core::core::splitter::split_file;
-use stem_splitter_core::{AudioData, SplitOptions};
-
-// Compute hex sha256 for arbitrary bytes
-fn sha256_hex(bytes: &[u8]) -> String {
-    let mut h = Sha256::new();
-    h.update(bytes);
-    hex::encode(h.finalize())
-}
-
-fn manifest_json(model_url: &str, sha_hex: &str) -> String {
-    format!(
-        r#"{{
-  "name": "mdx_mock",
-  "version": "1.0.0",
-  "backend": "onnx",
-  "sample_rate": 44100,
-  "window": 4096,
-  "hop": 2048,
-  "stems": ["vocals","drums","bass","other"],
-  "input_layout": "BCT",
-  "output_layout": "BSCT",
-  "artifacts": [
-    {{
-      "file": "mock.onnx",
-      "url": "{url}",
-      "sha256": "{sha}",
-      "size_bytes": 0
-    }}
-  ]
-}}"#,
-        url = model_url,
-        sha = sha_hex
-    )
-}
+use stem_splitter_core::core::audio::{read_audio, write_audio};
+use stem_splitter_core::core::splitter::{split_audio, split_file, split_files};
+use stem_splitter_core::{
+    set_split_progress_callback, AudioData, AudioMetadata, CancelToken, ComplementMethod,
+    DecodeDamage, GainStaging, MixConsistency, MultichannelMode, OutputFormat, OverlapWindow,
+    Picture, SplitOptions, SplitProgress, StemError,
+};
 
 #[test]
 fn split_file_produces_four_stems() {
     let tmp = tempdir().unwrap();
-    std::env::set_var("XDG_CACHE_HOME", tmp.path());
-
     let in_wav = tmp.path().join("in.wav");
     let out_dir = tmp.path().join("out");
     fs::create_dir_all(&out_dir).unwrap();
 
     let sr = 44_100u32;
-    let frames = 8000usize;
-    let mut samples = Vec::with_capacity(frames * 2);
-    for i in 0..frames {
-        let t = i as f32 / sr as f32;
-        samples.push((2.0 * PI * 440.0 * t).sin() * 0.2);
-        samples.push((2.0 * PI * 660.0 * t).sin() * 0.2);
+    write_stereo_sine(&in_wav, sr, 8000);
+
+    let res =
+        split_file(in_wav.to_str().unwrap(), mock_options(&out_dir)).expect("split_file failed");
+
+    let names: Vec<&str> = res.stems.keys().map(String::as_str).collect();
+    assert_eq!(names, ["vocals", "drums", "bass", "other"]);
+    assert!(res.path("vocals").unwrap().ends_with("in_vocals.wav"));
+
+    for p in stem_paths(&res) {
+        assert!(Path::new(p).exists(), "missing stem {p}");
+        let r = hound::WavReader::open(p).unwrap();
+        assert_eq!(r.spec().channels, 2);
+        assert_eq!(r.spec().sample_rate, sr);
+        assert!(r.into_samples::<i16>().count() > 0);
+    }
+}
+
+#[test]
+fn split_file_resamples_input_to_model_rate() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("in48k.wav");
+    let out_dir = tmp.path().join("out");
+
+    write_stereo_sine(&in_wav, 48_000, 9600);
+
+    let res =
+        split_file(in_wav.to_str().unwrap(), mock_options(&out_dir)).expect("split_file failed");
+
+    for p in stem_paths(&res) {
+        let r = hound::WavReader::open(p).unwrap();
+        assert_eq!(r.spec().sample_rate, 44_100);
+        assert_eq!(r.duration(), 8820);
+    }
+}
+
+#[test]
+fn split_file_can_resample_stems_back_to_source_rate() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("in96k.wav");
+    let out_dir = tmp.path().join("out");
+
+    let frames = 19_200usize;
+    write_stereo_sine(&in_wav, 96_000, frames);
+
+    let opts = SplitOptions {
+        resample_to_source_rate: true,
+        ..mock_options(&out_dir)
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    for p in stem_paths(&res) {
+        let r = hound::WavReader::open(p).unwrap();
+        assert_eq!(r.spec().sample_rate, 96_000);
+        assert_eq!(r.duration() as usize, frames);
+    }
+}
+
+#[test]
+fn overlap_add_reconstructs_identity_stems_without_seams() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("ola.wav");
+    write_stereo_sine(&in_wav, 44_100, 15_000);
+    let input = read_audio(&in_wav).unwrap();
+
+    for (overlap, window) in [
+        (0.25, OverlapWindow::Triangular),
+        (0.5, OverlapWindow::Linear),
+        (0.75, OverlapWindow::Triangular),
+    ] {
+        let out_dir = tmp.path().join(format!("out_{overlap}"));
+        let opts = SplitOptions {
+            overlap: Some(overlap),
+            overlap_window: window,
+            ..mock_options(&out_dir)
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+        let vocals = read_audio(res.path("vocals").unwrap()).unwrap();
+        assert_eq!(vocals.samples.len(), input.samples.len());
+        for (a, b) in vocals.samples.iter().zip(input.samples.iter()) {
+            assert!((a - b).abs() < 2e-4, "overlap {overlap}: {a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn split_file_rejects_out_of_range_overlap() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("in.wav");
+    write_stereo_sine(&in_wav, 44_100, 4000);
+
+    let opts = SplitOptions {
+        overlap: Some(1.0),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    assert!(split_file(in_wav.to_str().unwrap(), opts).is_err());
+}
+
+#[test]
+fn shifts_and_flips_are_undone_before_averaging() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("tta.wav");
+    write_stereo_sine(&in_wav, 44_100, 15_000);
+    let input = read_audio(&in_wav).unwrap();
+
+    let opts = SplitOptions {
+        shifts: 2,
+        flip_polarity: true,
+        flip_channels: true,
+        seed: Some(7),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    for p in stem_paths(&res) {
+        let stem = read_audio(p).unwrap();
+        assert_eq!(stem.samples.len(), input.samples.len());
+        for (a, b) in stem.samples.iter().zip(input.samples.iter()) {
+            assert!((a - b).abs() < 2e-4, "{a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn split_file_writes_only_selected_stems() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("sel.wav");
+    let out_dir = tmp.path().join("out");
+    fs::create_dir_all(&out_dir).unwrap();
+    write_stereo_sine(&in_wav, 44_100, 6000);
+
+    let opts = SplitOptions {
+        stems: vec!["Drums".into(), "vocals".into()],
+        ..mock_options(&out_dir)
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    let names: Vec<&str> = res.stems.keys().map(String::as_str).collect();
+    assert_eq!(names, ["vocals", "drums"]);
+    assert!(!out_dir.join("sel_bass.wav").exists());
+    assert!(!out_dir.join("sel_other.wav").exists());
+}
+
+#[test]
+fn two_stem_mode_writes_stem_and_complement() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("karaoke.wav");
+    write_stereo_sine(&in_wav, 44_100, 9000);
+    let input = read_audio(&in_wav).unwrap();
+
+    // The mock returns the input for every source, so the sum of the three
+    // others is 3x the input and the mixture minus vocals is silence.
+    for (complement, scale) in [(ComplementMethod::Sum, 3.0), (ComplementMethod::Minus, 0.0)] {
+        let out_dir = tmp.path().join(format!("out_{complement:?}"));
+        fs::create_dir_all(&out_dir).unwrap();
+        let opts = SplitOptions {
+            two_stems: Some("vocals".into()),
+            complement,
+            ..mock_options(&out_dir)
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+        let names: Vec<&str> = res.stems.keys().map(String::as_str).collect();
+        assert_eq!(names, ["vocals", "no_vocals"]);
+        assert!(!out_dir.join("karaoke_drums.wav").exists());
+
+        let rest = read_audio(res.path("no_vocals").unwrap()).unwrap();
+        assert_eq!(rest.samples.len(), input.samples.len());
+        for (a, b) in rest.samples.iter().zip(input.samples.iter()) {
+            assert!((a - scale * b).abs() < 1e-3, "{complement:?}: {a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn split_file_rejects_unknown_stems() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("in.wav");
+    write_stereo_sine(&in_wav, 44_100, 4000);
+
+    let opts = SplitOptions {
+        stems: vec!["kazoo".into()],
+        ..mock_options(&tmp.path().join("out"))
+    };
+    assert!(split_file(in_wav.to_str().unwrap(), opts).is_err());
+
+    let opts = SplitOptions {
+        two_stems: Some("kazoo".into()),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    assert!(split_file(in_wav.to_str().unwrap(), opts).is_err());
+}
+
+#[test]
+fn split_file_writes_flac_stems() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("lossless.wav");
+    write_stereo_sine(&in_wav, 44_100, 7000);
+    let input = read_audio(&in_wav).unwrap();
+
+    let opts = SplitOptions {
+        output_format: OutputFormat::Flac24,
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    for p in stem_paths(&res) {
+        assert!(p.ends_with(".flac"), "{p}");
+        let stem = read_audio(p).unwrap();
+        assert_eq!(stem.samples.len(), input.samples.len());
+    }
+}
+
+#[test]
+fn split_audio_returns_stems_in_memory() {
+    let tmp = tempdir().unwrap();
+    let out_dir = tmp.path().join("untouched");
+    let frames = 9000;
+    let samples: Vec<f32> = (0..frames)
+        .map(|i| (2.0 * PI * 440.0 * i as f32 / 44_100.0).sin() * 0.3)
+        .collect();
+    let audio = AudioData {
+        samples: samples.clone(),
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+
+    let stems = split_audio(&audio, mock_options(&out_dir)).expect("split_audio failed");
+
+    assert!(!out_dir.exists());
+    let names: Vec<&str> = stems.stems.keys().map(String::as_str).collect();
+    assert_eq!(names, ["vocals", "drums", "bass", "other"]);
+
+    // Mono input is fed to both model channels, and the mock echoes it back.
+    let vocals = stems.get("vocals").unwrap();
+    assert_eq!(vocals.sample_rate, 44_100);
+    assert_eq!(vocals.channels, 2);
+    assert_eq!(vocals.samples.len(), frames * 2);
+    for (frame, expected) in vocals.samples.chunks_exact(2).zip(samples.iter()) {
+        assert!((frame[0] - expected).abs() < 1e-5);
+        assert!((frame[1] - expected).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn split_audio_matches_split_file() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("same.wav");
+    write_stereo_sine(&in_wav, 48_000, 9600);
+    let input = read_audio(&in_wav).unwrap();
+
+    let opts = SplitOptions {
+        resample_to_source_rate: true,
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let files = split_file(in_wav.to_str().unwrap(), opts.clone()).unwrap();
+    let buffers = split_audio(&input, opts).unwrap();
+
+    for (name, stem) in &files.stems {
+        let from_file = read_audio(&stem.path).unwrap();
+        let in_memory = buffers.get(name).unwrap();
+        assert_eq!(in_memory.sample_rate, 48_000);
+        assert_eq!(in_memory.samples.len(), from_file.samples.len());
+        for (a, b) in in_memory.samples.iter().zip(from_file.samples.iter()) {
+            assert!((a - b).abs() < 1e-4, "{name}: {a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn cancelled_split_returns_cancelled_and_removes_partial_stems() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("long.wav");
+    let out_dir = tmp.path().join("out");
+    write_stereo_sine(&in_wav, 44_100, 20_000);
+
+    // Cancel from the progress callback once a couple of windows are written.
+    static CANCEL: OnceLock<CancelToken> = OnceLock::new();
+    let cancel = CANCEL.get_or_init(CancelToken::new);
+    set_split_progress_callback(|progress| {
+        if let SplitProgress::Chunks { done: 2, .. } = progress {
+            CANCEL.get().unwrap().cancel();
+        }
+    });
+
+    let opts = SplitOptions {
+        cancel: cancel.clone(),
+        ..mock_options(&out_dir)
+    };
+    let err =
+        split_file(in_wav.to_str().unwrap(), opts).expect_err("expected the split to be cancelled");
+    assert!(matches!(err, StemError::Cancelled), "got {err}");
+
+    let leftovers: Vec<_> = fs::read_dir(&out_dir).unwrap().collect();
+    assert!(leftovers.is_empty(), "left behind: {leftovers:?}");
+}
+
+#[test]
+fn split_file_writes_only_the_requested_range() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("long.wav");
+    write_stereo_sine(&in_wav, 44_100, 30_000);
+    let input = read_audio(&in_wav).unwrap();
+
+    let opts = SplitOptions {
+        start: Some(0.25),
+        end: Some(0.5),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    // 0.25s..0.5s at 44.1 kHz is frames 11025..22050.
+    let expected = &input.samples[11_025 * 2..22_050 * 2];
+    for path in stem_paths(&res) {
+        let stem = read_audio(path).unwrap();
+        assert_eq!(stem.samples.len(), expected.len(), "{path}");
+        for (a, b) in stem.samples.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 2e-4, "{path}: {a} vs {b}");
+        }
     }
+}
+
+#[test]
+fn range_is_exact_at_the_source_rate_after_resampling() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("hi_rate.wav");
+    write_stereo_sine(&in_wav, 48_000, 48_000);
+
+    let opts = SplitOptions {
+        start: Some(0.1),
+        end: Some(0.6),
+        resample_to_source_rate: true,
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    let vocals = read_audio(res.path("vocals").unwrap()).unwrap();
+    assert_eq!(vocals.sample_rate, 48_000);
+    assert_eq!(vocals.samples.len(), 24_000 * 2);
+}
+
+#[test]
+fn split_audio_range_runs_to_the_end_without_an_end_time() {
+    let frames = 20_000;
+    let samples: Vec<f32> = (0..frames)
+        .map(|i| (2.0 * PI * 220.0 * i as f32 / 44_100.0).sin() * 0.3)
+        .collect();
+    let audio = AudioData {
+        samples: samples.clone(),
+        sample_rate: 44_100,
+        channels: 1,
+        ..Default::default()
+    };
+
+    let opts = SplitOptions {
+        start: Some(0.2),
+        ..mock_options(Path::new("unused"))
+    };
+    let stems = split_audio(&audio, opts).expect("split_audio failed");
+
+    let drums = stems.get("drums").unwrap();
+    assert_eq!(drums.samples.len(), (frames - 8820) * 2);
+    for (frame, expected) in drums.samples.chunks_exact(2).zip(&samples[8820..]) {
+        assert!((frame[0] - expected).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn split_file_rejects_invalid_ranges() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("short.wav");
+    write_stereo_sine(&in_wav, 44_100, 4410);
+
+    for (start, end) in [
+        (Some(0.5), Some(0.5)),
+        (Some(0.5), Some(0.2)),
+        (Some(-1.0), None),
+        (None, Some(f64::NAN)),
+        (Some(2.0), None),
+    ] {
+        let opts = SplitOptions {
+            start,
+            end,
+            ..mock_options(&tmp.path().join("out"))
+        };
+        let err = split_file(in_wav.to_str().unwrap(), opts)
+            .expect_err(&format!("{start:?}..{end:?} should be rejected"));
+        assert!(err.to_string().contains("time"), "{err}");
+    }
+}
+
+#[test]
+fn split_files_reports_each_input_and_continues_past_failures() {
+    let tmp = tempdir().unwrap();
+    let first = tmp.path().join("first.wav");
+    let missing = tmp.path().join("missing.wav");
+    let third = tmp.path().join("third.wav");
+    write_stereo_sine(&first, 44_100, 9000);
+    write_stereo_sine(&third, 48_000, 7000);
+    let inputs = [&first, &missing, &third].map(|p| p.to_string_lossy().into_owned());
+
+    let out_dir = tmp.path().join("out");
+    let batch = split_files(&inputs, mock_options(&out_dir)).expect("batch failed");
+
+    let order: Vec<&str> = batch.items.iter().map(|item| item.input.as_str()).collect();
+    assert_eq!(order, inputs.iter().map(String::as_str).collect::<Vec<_>>());
+    assert_eq!(batch.succeeded().count(), 2);
+    let failed: Vec<&str> = batch.failed().map(|(input, _)| input).collect();
+    assert_eq!(failed, [inputs[1].as_str()]);
+
+    // Batch output matches splitting each file on its own.
+    for (input, result) in batch.succeeded() {
+        let single_dir = tmp.path().join("single");
+        let single = split_file(input, mock_options(&single_dir)).unwrap();
+        assert_eq!(result.stems.len(), 4);
+        for (name, stem) in &result.stems {
+            let batched = read_audio(&stem.path).unwrap();
+            let alone = read_audio(single.path(name).unwrap()).unwrap();
+            assert_eq!(batched.samples, alone.samples, "{input}: {name}");
+        }
+    }
+}
+
+#[test]
+fn split_files_applies_the_time_range_to_every_input() {
+    let tmp = tempdir().unwrap();
+    let inputs: Vec<String> = (0..3)
+        .map(|i| {
+            let path = tmp.path().join(format!("track{i}.wav"));
+            write_stereo_sine(&path, 44_100, 30_000 + i * 1000);
+            path.to_string_lossy().into_owned()
+        })
+        .collect();
+
+    let opts = SplitOptions {
+        start: Some(0.25),
+        end: Some(0.5),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let batch = split_files(&inputs, opts).expect("batch failed");
+
+    for item in &batch.items {
+        let input = read_audio(&item.input).unwrap();
+        let expected = &input.samples[11_025 * 2..22_050 * 2];
+        let result = item.result.as_ref().expect("split failed");
+        let vocals = read_audio(result.path("vocals").unwrap()).unwrap();
+        assert_eq!(vocals.samples.len(), expected.len(), "{}", item.input);
+        for (a, b) in vocals.samples.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 2e-4, "{}: {a} vs {b}", item.input);
+        }
+    }
+}
+
+#[test]
+fn batched_inference_matches_one_window_per_call() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("batch.wav");
+    // 11 steps of 2048 frames, so batches of 2 and 3 end ragged.
+    write_stereo_sine(&in_wav, 44_100, 21_000);
+
+    let split = |batch_size: Option<usize>| {
+        let opts = SplitOptions {
+            batch_size,
+            shifts: 1,
+            flip_channels: true,
+            seed: Some(7),
+            ..mock_options(&tmp.path().join(format!("out_{batch_size:?}")))
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+        read_audio(res.path("vocals").unwrap()).unwrap().samples
+    };
+
+    let single = split(Some(1));
+    for batch_size in [None, Some(2), Some(3), Some(64)] {
+        assert_eq!(split(batch_size), single, "batch size {batch_size:?}");
+    }
+}
+
+#[test]
+fn split_file_rejects_zero_batch_size() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("in.wav");
+    write_stereo_sine(&in_wav, 44_100, 4000);
+
+    let opts = SplitOptions {
+        batch_size: Some(0),
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let err = split_file(in_wav.to_str().unwrap(), opts).unwrap_err();
+    assert!(err.to_string().contains("batch_size"), "{err}");
+}
+
+#[test]
+fn split_file_reports_stem_levels_and_mix_residual() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("levels.wav");
+    write_stereo_sine(&in_wav, 44_100, 44_100);
+
+    let res = split_file(
+        in_wav.to_str().unwrap(),
+        mock_options(&tmp.path().join("out")),
+    )
+    .expect("split_file failed");
+
+    // The mock model returns the mixture for every source, so every stem
+    // carries the 0.2-amplitude test tone.
+    for (name, stem) in &res.stems {
+        let stats = stem.stats;
+        assert!((stats.sample_peak - 0.2).abs() < 1e-3, "{name}: {stats:?}");
+        assert!(stats.true_peak >= stats.sample_peak, "{name}: {stats:?}");
+        assert!(
+            (stats.rms - 0.2 / 2f32.sqrt()).abs() < 1e-3,
+            "{name}: {stats:?}"
+        );
+        assert!(
+            (-20.0..-10.0).contains(&stats.integrated_lufs),
+            "{name}: {stats:?}"
+        );
+        assert_eq!(stats.clipped_samples, 0, "{name}");
+    }
+
+    // Four copies of the mixture sum to 4x the mixture: a residual of -3x.
+    let residual = res.mix_residual;
+    assert!(
+        (residual.relative_db - 20.0 * 3f32.log10()).abs() < 0.05,
+        "{residual:?}"
+    );
+    assert!(
+        (residual.rms - 3.0 * 0.2 / 2f32.sqrt()).abs() < 1e-2,
+        "{residual:?}"
+    );
+}
+
+#[test]
+fn gain_staging_records_a_gain_that_can_be_undone() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("hot.wav");
+    write_stereo_sine(&in_wav, 44_100, 20_000);
+    let plain = split_file(
+        in_wav.to_str().unwrap(),
+        SplitOptions {
+            output_format: OutputFormat::WavF32,
+            ..mock_options(&tmp.path().join("plain"))
+        },
+    )
+    .unwrap();
+
+    for (dir, gain) in [
+        ("common", GainStaging::Common { ceiling_db: -20.0 }),
+        ("peak", GainStaging::NormalizePeak { peak_db: -3.0 }),
+        ("loudness", GainStaging::NormalizeLoudness { lufs: -30.0 }),
+    ] {
+        let res = split_file(
+            in_wav.to_str().unwrap(),
+            SplitOptions {
+                gain,
+                output_format: OutputFormat::WavF32,
+                ..mock_options(&tmp.path().join(dir))
+            },
+        )
+        .unwrap();
+
+        for (name, stem) in &res.stems {
+            let plain_stem = &plain.stems[name];
+            let undo = 10f32.powf(-stem.gain_db / 20.0);
+            let gained = read_audio(&stem.path).unwrap().samples;
+            let original = read_audio(&plain_stem.path).unwrap().samples;
+            assert_eq!(gained.len(), original.len(), "{dir}/{name}");
+            for (a, b) in gained.iter().zip(&original) {
+                assert!((a * undo - b).abs() < 1e-5, "{dir}/{name}: {a} vs {b}");
+            }
+
+            let stats = stem.stats;
+            match gain {
+                GainStaging::Common { .. } => {
+                    assert!(
+                        (stats.true_peak - 0.1).abs() < 1e-3,
+                        "{dir}/{name}: {stats:?}"
+                    )
+                }
+                GainStaging::NormalizePeak { .. } => {
+                    assert!(
+                        (stats.true_peak - 0.708).abs() < 1e-3,
+                        "{dir}/{name}: {stats:?}"
+                    )
+                }
+                _ => assert!(
+                    (stats.integrated_lufs + 30.0).abs() < 1e-3,
+                    "{dir}/{name}: {stats:?}"
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn limiter_keeps_stems_under_the_ceiling() {
+    let samples: Vec<f32> = (0..20_000)
+        .flat_map(|i| {
+            let x = (i as f32 * 0.05).sin() * 1.5;
+            [x, x * 0.5]
+        })
+        .collect();
     let audio = AudioData {
         samples,
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+
+    let stems = split_audio(
+        &audio,
+        SplitOptions {
+            gain: GainStaging::Limit { ceiling_db: -1.0 },
+            ..mock_options(Path::new("unused"))
+        },
+    )
+    .unwrap();
+
+    let ceiling = 10f32.powf(-1.0 / 20.0);
+    for (name, stem) in &stems.stems {
+        assert_eq!(stem.samples.len(), audio.samples.len(), "{name}");
+        let peak = stem.samples.iter().fold(0f32, |m, x| m.max(x.abs()));
+        assert!(
+            peak <= ceiling && peak > 0.95 * ceiling,
+            "{name}: peak {peak}"
+        );
+    }
+}
+
+#[test]
+fn wiener_refinement_shares_the_mixture_between_identical_stems() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("wiener.wav");
+    write_stereo_sine(&in_wav, 44_100, 44_100);
+
+    let opts = SplitOptions {
+        wiener_iterations: 2,
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    // The mock model returns the mixture for every source, so the filter
+    // finds four identical models and gives each a quarter of the mixture.
+    for (name, stem) in &res.stems {
+        let stats = stem.stats;
+        assert!((stats.sample_peak - 0.05).abs() < 2e-3, "{name}: {stats:?}");
+    }
+    let residual = res.mix_residual;
+    assert!(residual.relative_db < -30.0, "{residual:?}");
+}
+
+#[test]
+fn consistent_stems_sum_back_to_the_input() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("consistent.wav");
+    write_stereo_sine(&in_wav, 44_100, 44_100);
+    let input = read_audio(in_wav.to_str().unwrap()).unwrap().samples;
+
+    for method in [MixConsistency::Equal, MixConsistency::Energy] {
+        let opts = SplitOptions {
+            consistency: method,
+            output_format: OutputFormat::WavF32,
+            ..mock_options(&tmp.path().join(format!("{method:?}")))
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+        // The mock model returns the mixture for every source, four times
+        // too much in total, so each stem is left with a quarter of it.
+        let stems: Vec<Vec<f32>> = stem_paths(&res)
+            .iter()
+            .map(|path| read_audio(path).unwrap().samples)
+            .collect();
+        for (i, &sample) in input.iter().enumerate() {
+            let sum: f32 = stems.iter().map(|stem| stem[i]).sum();
+            assert!((sum - sample).abs() < 1e-5, "{method:?} sample {i}");
+            assert!((stems[0][i] - sample / 4.0).abs() < 1e-5, "{method:?}");
+        }
+        assert!(
+            res.mix_residual.relative_db < -80.0,
+            "{:?}",
+            res.mix_residual
+        );
+    }
+}
+
+#[test]
+fn silent_windows_skip_the_model_and_are_reported() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("gap.wav");
+    // A tone, a long gap of digital silence, and the tone again.
+    let tone = |i: usize| (2.0 * PI * 440.0 * i as f32 / 44_100.0).sin() * 0.2;
+    let samples: Vec<f32> = (0..60_000)
+        .flat_map(|i| {
+            let x = if (10_000..50_000).contains(&i) {
+                0.0
+            } else {
+                tone(i)
+            };
+            [x, x]
+        })
+        .collect();
+    let audio = AudioData {
+        samples,
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+    write_audio(in_wav.to_str().unwrap(), &audio).unwrap();
+    let input = read_audio(in_wav.to_str().unwrap()).unwrap().samples;
+
+    // Silent windows leave batches short, which must not stall the pipeline.
+    for batch_size in [1, 3] {
+        let opts = SplitOptions {
+            batch_size: Some(batch_size),
+            output_format: OutputFormat::WavF32,
+            skip_silence: Some(-80.0),
+            ..mock_options(&tmp.path().join(format!("batch{batch_size}")))
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+        // 4096-frame windows every 2048 frames: windows 5 to 22 lie in the gap.
+        assert_eq!(res.windows, 30, "batch {batch_size}");
+        assert_eq!(res.silent_windows, 18, "batch {batch_size}");
+
+        // The mock model returns the mixture, so every stem matches the input
+        // up to the dither in the gap, which is below the silence threshold.
+        for path in stem_paths(&res) {
+            let stem = read_audio(path).unwrap().samples;
+            assert_eq!(stem.len(), input.len());
+            for (a, b) in stem.iter().zip(&input) {
+                assert!((a - b).abs() < 1e-4, "{path}");
+            }
+        }
+    }
+}
+
+#[test]
+fn stems_carry_the_source_tags_with_the_stem_in_the_title() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("tagged.wav");
+    let sr = 48_000;
+    let metadata = AudioMetadata {
+        tags: vec![
+            ("TITLE".into(), "Song".into()),
+            ("ARTIST".into(), "Band".into()),
+            ("ISRC".into(), "GBAYE0000001".into()),
+        ],
+        pictures: vec![Picture {
+            media_type: "image/jpeg".into(),
+            picture_type: 3,
+            description: String::new(),
+            data: vec![0xff, 0xd8, 0xff, 0xe0],
+        }],
+        // Ten seconds past midnight.
+        time_reference: Some(10 * sr as u64),
+    };
+    let audio = AudioData {
+        samples: vec![0.1; 20_000 * 2],
         sample_rate: sr,
         channels: 2,
+        metadata: metadata.clone(),
+        ..Default::default()
     };
     write_audio(in_wav.to_str().unwrap(), &audio).unwrap();
 
-    let server = MockServer::start();
-    let model_body = b"this is the mock onnx payload";
-    let model_sha = sha256_hex(model_body);
+    // The stems start 0.1 s in and are written at the model's 44.1 kHz.
+    let expected_time_reference = 441_000 + 4_410;
+    for format in [OutputFormat::Wav16, OutputFormat::Flac16] {
+        let opts = SplitOptions {
+            start: Some(0.1),
+            output_format: format,
+            ..mock_options(&tmp.path().join(format.extension()))
+        };
+        let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
 
-    let _model = server.mock(|when, then| {
-        when.method(GET).path("/mock.onnx");
-        then.status(200)
-            .header("Content-Length", model_body.len().to_string().as_str())
-            .body(model_body.as_slice());
-    });
+        for (name, stem) in &res.stems {
+            let tags = read_audio(&stem.path).unwrap().metadata;
+            assert_eq!(tags.get("TITLE"), Some(format!("Song ({name})").as_str()));
+            assert_eq!(tags.get("ARTIST"), Some("Band"));
+            assert_eq!(tags.get("ISRC"), Some("GBAYE0000001"));
+            assert_eq!(tags.pictures, metadata.pictures);
+            assert_eq!(tags.time_reference, Some(expected_time_reference));
+        }
+    }
 
-    let _manifest = server.mock(|when, then| {
-        when.method(GET).path("/m.json");
-        then.status(200)
-            .header("Content-Type", "application/json")
-            .body(manifest_json(
-                &format!("{}/mock.onnx", server.base_url()),
-                &model_sha,
-            ));
-    });
+    let stems = split_audio(&audio, mock_options(Path::new("unused"))).unwrap();
+    let bass = stems.get("bass").unwrap();
+    assert_eq!(bass.metadata.get("TITLE"), Some("Song (bass)"));
+    assert_eq!(bass.metadata.time_reference, Some(441_000));
+}
 
-    let manifest_url = format!("{}/m.json", server.base_url());
+#[test]
+fn decode_damage_of_each_input_is_reported() {
+    let tmp = tempdir().unwrap();
+    let clean = tmp.path().join("clean.wav");
+    let corrupt = tmp.path().join("corrupt.m4a");
+    let out_dir = tmp.path().join("out");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    write_stereo_sine(&clean, 44_100, 8000);
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tone_aac.m4a");
+    let mut bytes = fs::read(fixture).unwrap();
+    let mid = bytes.len() / 2;
+    for byte in &mut bytes[mid..mid + 64] {
+        *byte ^= 0xa5;
+    }
+    fs::write(&corrupt, bytes).unwrap();
+
+    // The second input is decoded ahead on the prefetch thread.
+    let inputs = [clean.to_str().unwrap(), corrupt.to_str().unwrap()];
+    let batch = split_files(&inputs, mock_options(&out_dir)).expect("split_files failed");
+    let damage: Vec<DecodeDamage> = batch
+        .items
+        .iter()
+        .map(|item| item.result.as_ref().expect("split failed").decode_damage)
+        .collect();
+    assert!(damage[0].is_clean());
+    assert!(damage[1].skipped_packets >= 1, "{:?}", damage[1]);
+    assert!(damage[1].dropped_frames > 0);
+
+    let single = split_file(corrupt.to_str().unwrap(), mock_options(&out_dir)).unwrap();
+    assert_eq!(single.decode_damage, damage[1]);
+}
+
+#[test]
+fn surround_input_is_downmixed_before_separation() {
+    let tmp = tempdir().unwrap();
+    let audio = surround_audio(9000);
+
+    let stems = split_audio(&audio, mock_options(tmp.path())).expect("split_audio failed");
+
+    // The mock echoes its input: the ITU downmix, without the LFE.
+    let vocals = stems.get("vocals").unwrap();
+    assert_eq!(vocals.channels, 2);
+    assert_eq!(vocals.samples.len(), 9000 * 2);
+    let g = std::f32::consts::FRAC_1_SQRT_2;
+    for (frame, input) in vocals
+        .samples
+        .chunks_exact(2)
+        .zip(audio.samples.chunks_exact(6))
+    {
+        let left = input[0] + g * input[2] + g * input[4];
+        let right = input[1] + g * input[2] + g * input[5];
+        assert!((frame[0] - left).abs() < 1e-5, "{} vs {left}", frame[0]);
+        assert!((frame[1] - right).abs() < 1e-5, "{} vs {right}", frame[1]);
+    }
+}
+
+#[test]
+fn per_pair_separation_keeps_the_input_channels() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("surround.wav");
+    let audio = surround_audio(9000);
+    write_audio(in_wav.to_str().unwrap(), &audio).unwrap();
+    let input = read_audio(&in_wav).unwrap();
 
     let opts = SplitOptions {
-        model_name: "ignored".into(),
-        manifest_url_override: Some(manifest_url),
-        output_dir: out_dir.to_string_lossy().into(),
+        multichannel: MultichannelMode::PerPair,
+        output_format: OutputFormat::WavF32,
+        gain: GainStaging::Limit { ceiling_db: -1.0 },
+        two_stems: Some("vocals".into()),
+        complement: ComplementMethod::Minus,
+        ..mock_options(&tmp.path().join("out"))
     };
-
     let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
 
-    for p in [
-        &res.vocals_path,
-        &res.drums_path,
-        &res.bass_path,
-        &res.other_path,
-    ] {
-        assert!(std::path::Path::new(p).exists(), "missing stem {p}");
-        let r = hound::WavReader::open(p).unwrap();
-        assert_eq!(r.spec().channels, 2);
-        assert_eq!(r.spec().sample_rate, sr);
-        assert!(r.into_samples::<i16>().count() > 0);
+    // Front, surround and the mono centre and LFE are separated on their own
+    // and land back on their own channels.
+    let vocals = read_audio(res.path("vocals").unwrap()).unwrap();
+    assert_eq!(vocals.channels, 6);
+    assert_eq!(vocals.samples.len(), input.samples.len());
+    for (a, b) in vocals.samples.iter().zip(input.samples.iter()) {
+        assert!((a - b).abs() < 1e-4, "{a} vs {b}");
     }
+    let rest = read_audio(res.path("no_vocals").unwrap()).unwrap();
+    assert_eq!(rest.channels, 6);
+    assert!(rest.samples.iter().all(|s| s.abs() < 1e-4));
+}
+
+#[test]
+fn a_chosen_channel_pair_is_separated_alone() {
+    let tmp = tempdir().unwrap();
+    let audio = surround_audio(9000);
+
+    let opts = SplitOptions {
+        multichannel: MultichannelMode::Pair { left: 4, right: 5 },
+        ..mock_options(tmp.path())
+    };
+    let stems = split_audio(&audio, opts).expect("split_audio failed");
+    let vocals = stems.get("vocals").unwrap();
+    assert_eq!(vocals.channels, 2);
+    for (frame, input) in vocals
+        .samples
+        .chunks_exact(2)
+        .zip(audio.samples.chunks_exact(6))
+    {
+        assert!((frame[0] - input[4]).abs() < 1e-5);
+        assert!((frame[1] - input[5]).abs() < 1e-5);
+    }
+
+    let opts = SplitOptions {
+        multichannel: MultichannelMode::Pair { left: 6, right: 7 },
+        ..mock_options(tmp.path())
+    };
+    let err = split_audio(&audio, opts).unwrap_err().to_string();
+    assert!(
+        err.contains("6,7 is out of range for a 6-channel input"),
+        "{err}"
+    );
+}
+
+#[test]
+fn quiet_windows_run_the_model_when_silence_skipping_is_off() {
+    let tmp = tempdir().unwrap();
+    // A reverb-tail level tone at -100 dBFS.
+    let samples: Vec<f32> = (0..9000)
+        .flat_map(|i| {
+            let x = (2.0 * PI * 440.0 * i as f32 / 44_100.0).sin() * 1e-5;
+            [x, -x]
+        })
+        .collect();
+    let audio = AudioData {
+        samples: samples.clone(),
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+    let opts = |skip_silence| SplitOptions {
+        skip_silence,
+        ..mock_options(tmp.path())
+    };
+
+    let skipped = split_audio(&audio, opts(Some(-80.0))).unwrap();
+    assert!(skipped
+        .get("vocals")
+        .unwrap()
+        .samples
+        .iter()
+        .all(|&s| s == 0.0));
+
+    // Skipping is off by default, so quiet windows go through the model.
+    for opts in [mock_options(tmp.path()), opts(Some(-120.0))] {
+        let skip_silence = opts.skip_silence;
+        let stems = split_audio(&audio, opts).unwrap();
+        let vocals = stems.get("vocals").unwrap();
+        assert_eq!(vocals.samples.len(), samples.len());
+        for (a, b) in vocals.samples.iter().zip(&samples) {
+            assert!((a - b).abs() < 1e-8, "{skip_silence:?}: {a} vs {b}");
+        }
+    }
+}
+
+#[test]
+fn a_channel_pair_applies_to_stereo_input_and_must_exist() {
+    let tmp = tempdir().unwrap();
+    let samples: Vec<f32> = (0..9000)
+        .flat_map(|i| {
+            let t = i as f32 / 44_100.0;
+            [
+                (2.0 * PI * 440.0 * t).sin() * 0.2,
+                (2.0 * PI * 660.0 * t).sin() * 0.2,
+            ]
+        })
+        .collect();
+    let audio = AudioData {
+        samples,
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+    let opts = |left, right| SplitOptions {
+        multichannel: MultichannelMode::Pair { left, right },
+        ..mock_options(tmp.path())
+    };
+
+    let stems = split_audio(&audio, opts(1, 0)).expect("split_audio failed");
+    let vocals = stems.get("vocals").unwrap();
+    for (frame, input) in vocals
+        .samples
+        .chunks_exact(2)
+        .zip(audio.samples.chunks_exact(2))
+    {
+        assert!((frame[0] - input[1]).abs() < 1e-5);
+        assert!((frame[1] - input[0]).abs() < 1e-5);
+    }
+
+    let err = split_audio(&audio, opts(2, 3)).unwrap_err().to_string();
+    assert!(
+        err.contains("2,3 is out of range for a 2-channel input"),
+        "{err}"
+    );
+    let mono = AudioData {
+        samples: audio.samples.iter().step_by(2).copied().collect(),
+        channels: 1,
+        ..audio.clone()
+    };
+    let err = split_audio(&mono, opts(0, 1)).unwrap_err().to_string();
+    assert!(
+        err.contains("0,1 is out of range for a 1-channel input"),
+        "{err}"
+    );
+}
+
+#[test]
+fn surround_stem_loudness_uses_the_channel_weights() {
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("surround.wav");
+    // 1 kHz at -23 dBFS on every channel of a 5.1 input but the LFE, which
+    // is 12 dB hotter.
+    let amplitude = 10f32.powf(-23.0 / 20.0);
+    let samples = (0..88_200)
+        .flat_map(|i| {
+            let x = (2.0 * PI * 1000.0 * i as f32 / 44_100.0).sin() * amplitude;
+            [x, x, x, 4.0 * x, x, x]
+        })
+        .collect();
+    let audio = AudioData {
+        samples,
+        sample_rate: 44_100,
+        channels: 6,
+        ..Default::default()
+    };
+    write_audio(in_wav.to_str().unwrap(), &audio).unwrap();
+
+    let opts = SplitOptions {
+        multichannel: MultichannelMode::PerPair,
+        output_format: OutputFormat::WavF32,
+        ..mock_options(&tmp.path().join("out"))
+    };
+    let res = split_file(in_wav.to_str().unwrap(), opts).expect("split_file failed");
+
+    // BS.1770: the surrounds count 1.41 times and the LFE not at all.
+    let expected = -23.0 + 10.0 * ((3.0 + 2.0 * 1.41f32) / 2.0).log10();
+    let stats = res.stems["vocals"].stats;
+    assert!(
+        (stats.integrated_lufs - expected).abs() < 0.1,
+        "{} vs {expected}",
+        stats.integrated_lufs
+    );
 }
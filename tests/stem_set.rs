@@ -0,0 +1,42 @@
+#![cfg(feature = "engine-mock")]
+
+// Lives in its own test binary because the engine mock keeps the first
+// manifest it sees for the whole process.
+
+mod common;
+
+use std::path::Path;
+use tempfile::tempdir;
+
+use common::MockModel;
+use stem_splitter_core::core::audio::write_audio;
+use stem_splitter_core::core::splitter::split_file;
+use stem_splitter_core::AudioData;
+
+#[test]
+fn split_result_follows_manifest_stems() {
+    let model = MockModel::serve(&["drums", "bass", "other", "vocals", "guitar", "piano"]);
+
+    let tmp = tempdir().unwrap();
+    let in_wav = tmp.path().join("song.wav");
+    let audio = AudioData {
+        samples: vec![0.1; 2 * 6000],
+        sample_rate: 44_100,
+        channels: 2,
+        ..Default::default()
+    };
+    write_audio(in_wav.to_str().unwrap(), &audio).unwrap();
+
+    let res =
+        split_file(in_wav.to_str().unwrap(), model.options(tmp.path())).expect("split_file failed");
+
+    let names: Vec<&str> = res.stems.keys().map(String::as_str).collect();
+    assert_eq!(
+        names,
+        ["drums", "bass", "other", "vocals", "guitar", "piano"]
+    );
+    for (name, stem) in &res.stems {
+        assert!(stem.path.ends_with(&format!("song_{name}.wav")));
+        assert!(Path::new(&stem.path).exists(), "missing stem {name}");
+    }
+}